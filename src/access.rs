@@ -0,0 +1,207 @@
+/*
+Pbfextractor creates graph files for the cycle-routing projects from pbf and srtm data
+Copyright (C) 2018  Florian Barth
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Evaluation of the OSM access tags.
+//!
+//! Access keys form a hierarchy from the general `access` down to single
+//! transport modes, e.g. `access` > `vehicle` > `motor_vehicle` >
+//! `motorcar`. The most specific key of a mode chain with a known value
+//! decides.
+
+#[cfg(test)]
+use super::testing::tags;
+
+use osmpbfreader::Tags;
+use serde::Deserialize;
+
+/// A chain of access keys from the most general to the most specific one.
+pub type ModeChain = &'static [&'static str];
+
+pub const CAR: ModeChain = &["access", "vehicle", "motor_vehicle", "motorcar"];
+pub const HGV: ModeChain = &["access", "vehicle", "motor_vehicle", "hgv"];
+pub const BICYCLE: ModeChain = &["access", "vehicle", "bicycle"];
+pub const FOOT: ModeChain = &["access", "foot"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Access {
+    Allowed,
+    /// Only allowed to reach destinations on the way, e.g. `destination`
+    /// or `delivery`.
+    Destination,
+    Forbidden,
+}
+
+impl Access {
+    /// The meaning of an access value, `None` for unknown values.
+    pub fn from_value(value: &str) -> Option<Access> {
+        match value {
+            "yes" | "permissive" | "designated" | "official" | "discouraged" => {
+                Some(Access::Allowed)
+            }
+            "destination" | "delivery" | "customers" => Some(Access::Destination),
+            "no" | "private" | "agricultural" | "forestry" | "permit" | "use_sidepath"
+            | "military" => Some(Access::Forbidden),
+            _ => None,
+        }
+    }
+}
+
+/// The direction of travel relative to the direction of the way.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Direction {
+    Forward,
+    Backward,
+}
+
+/// The directions in which a way can be used.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Directions {
+    pub forward: bool,
+    pub backward: bool,
+}
+
+impl Directions {
+    pub const BOTH: Directions = Directions {
+        forward: true,
+        backward: true,
+    };
+    pub const FORWARD: Directions = Directions {
+        forward: true,
+        backward: false,
+    };
+    pub const BACKWARD: Directions = Directions {
+        forward: false,
+        backward: true,
+    };
+    pub const NONE: Directions = Directions {
+        forward: false,
+        backward: false,
+    };
+
+    /// The meaning of a `oneway` value, `None` for unknown values.
+    /// Reversible ways change their direction over time and are not used.
+    pub fn from_oneway(value: &str) -> Option<Directions> {
+        match value {
+            "yes" | "true" | "1" => Some(Directions::FORWARD),
+            "-1" | "reverse" => Some(Directions::BACKWARD),
+            "no" | "false" | "0" => Some(Directions::BOTH),
+            "reversible" | "alternating" => Some(Directions::NONE),
+            _ => None,
+        }
+    }
+}
+
+/// The directions in which `mode` can use a way. A `oneway:<mode>` tag wins
+/// over `oneway`, without any motorways and roundabouts are one-way.
+pub fn directions(tags: &Tags, mode: Option<&str>) -> Directions {
+    let mode_specific = mode.and_then(|m| tags.get(format!("oneway:{}", m).as_str()));
+    if let Some(d) = mode_specific.and_then(|v| Directions::from_oneway(v)) {
+        return d;
+    }
+    if let Some(d) = tags.get("oneway").and_then(|v| Directions::from_oneway(v)) {
+        return d;
+    }
+    let implied = tags
+        .get("highway")
+        .map(|h| h == "motorway")
+        .unwrap_or(false)
+        || tags
+            .get("junction")
+            .map(|j| j == "roundabout" || j == "circular")
+            .unwrap_or(false);
+    if implied {
+        Directions::FORWARD
+    } else {
+        Directions::BOTH
+    }
+}
+
+/// The access of a way for the mode given by `chain`, together with the
+/// key that decided it. `None` if no key of the chain is tagged.
+pub fn access_with_key<'a, S: AsRef<str>>(
+    tags: &Tags,
+    chain: &'a [S],
+) -> Option<(&'a str, Access)> {
+    chain.iter().rev().find_map(|key| {
+        let key = key.as_ref();
+        tags.get(key)
+            .and_then(|v| Access::from_value(v))
+            .map(|a| (key, a))
+    })
+}
+
+/// The access of a way for the mode given by `chain`.
+pub fn access<S: AsRef<str>>(tags: &Tags, chain: &[S]) -> Option<Access> {
+    access_with_key(tags, chain).map(|(_, a)| a)
+}
+
+#[test]
+fn most_specific_key_decides() {
+    let way = tags(&[("access", "no"), ("bicycle", "yes")]);
+    assert_eq!(Some(Access::Forbidden), access(&way, CAR));
+    assert_eq!(Some(Access::Allowed), access(&way, BICYCLE));
+    assert_eq!(
+        Some(("bicycle", Access::Allowed)),
+        access_with_key(&way, BICYCLE)
+    );
+
+    let way = tags(&[("motor_vehicle", "destination"), ("hgv", "no")]);
+    assert_eq!(Some(Access::Destination), access(&way, CAR));
+    assert_eq!(Some(Access::Forbidden), access(&way, HGV));
+    assert_eq!(None, access(&way, BICYCLE));
+    assert_eq!(None, access(&way, FOOT));
+
+    // unknown values leave the decision to more general keys
+    let way = tags(&[("vehicle", "private"), ("motorcar", "unknown")]);
+    assert_eq!(Some(Access::Forbidden), access(&way, CAR));
+    let chain = vec!["access".to_owned(), "vehicle".to_owned()];
+    assert_eq!(Some(Access::Forbidden), access(&way, &chain));
+}
+
+#[test]
+fn oneway_directions() {
+    assert_eq!(
+        Directions::BOTH,
+        directions(&tags(&[("highway", "primary")]), None)
+    );
+    assert_eq!(
+        Directions::FORWARD,
+        directions(&tags(&[("highway", "motorway")]), None)
+    );
+    assert_eq!(
+        Directions::BOTH,
+        directions(&tags(&[("highway", "motorway"), ("oneway", "no")]), None)
+    );
+    assert_eq!(
+        Directions::FORWARD,
+        directions(&tags(&[("junction", "roundabout")]), Some("bicycle"))
+    );
+    assert_eq!(
+        Directions::BACKWARD,
+        directions(&tags(&[("oneway", "-1")]), None)
+    );
+    assert_eq!(
+        Directions::NONE,
+        directions(&tags(&[("oneway", "reversible")]), None)
+    );
+
+    let way = tags(&[("oneway", "yes"), ("oneway:bicycle", "no")]);
+    assert_eq!(Directions::FORWARD, directions(&way, None));
+    assert_eq!(Directions::FORWARD, directions(&way, Some("motor_vehicle")));
+    assert_eq!(Directions::BOTH, directions(&way, Some("bicycle")));
+}
@@ -0,0 +1,216 @@
+/*
+Pbfextractor creates graph files for the cycle-routing projects from pbf and srtm data
+Copyright (C) 2018  Florian Barth
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use super::pbf::{Latitude, Longitude};
+
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+#[derive(Debug)]
+pub enum AreaError {
+    Io(std::io::Error),
+    InvalidBoundingBox(String),
+    InvalidPolygon(usize, String),
+}
+
+impl fmt::Display for AreaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AreaError::Io(e) => write!(f, "could not read polygon file: {}", e),
+            AreaError::InvalidBoundingBox(b) => write!(
+                f,
+                "invalid bounding box '{}', expected minlon,minlat,maxlon,maxlat",
+                b
+            ),
+            AreaError::InvalidPolygon(line, msg) => {
+                write!(f, "invalid polygon file in line {}: {}", line, msg)
+            }
+        }
+    }
+}
+
+impl std::error::Error for AreaError {}
+
+/// A ring of a polygon, given as (longitude, latitude) pairs.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Ring {
+    points: Vec<(Longitude, Latitude)>,
+    hole: bool,
+}
+
+impl Ring {
+    fn contains(&self, lat: Latitude, lng: Longitude) -> bool {
+        let mut inside = false;
+        let mut j = self.points.len() - 1;
+        for i in 0..self.points.len() {
+            let (xi, yi) = self.points[i];
+            let (xj, yj) = self.points[j];
+            if (yi > lat) != (yj > lat) && lng < (xj - xi) * (lat - yi) / (yj - yi) + xi {
+                inside = !inside;
+            }
+            j = i;
+        }
+        inside
+    }
+}
+
+/// The area to clip the graph to.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Area {
+    BoundingBox {
+        min_lng: Longitude,
+        min_lat: Latitude,
+        max_lng: Longitude,
+        max_lat: Latitude,
+    },
+    Polygon(Vec<Ring>),
+}
+
+impl Area {
+    pub fn contains(&self, lat: Latitude, lng: Longitude) -> bool {
+        match self {
+            Area::BoundingBox {
+                min_lng,
+                min_lat,
+                max_lng,
+                max_lat,
+            } => lat >= *min_lat && lat <= *max_lat && lng >= *min_lng && lng <= *max_lng,
+            Area::Polygon(rings) => {
+                rings.iter().any(|r| !r.hole && r.contains(lat, lng))
+                    && !rings.iter().any(|r| r.hole && r.contains(lat, lng))
+            }
+        }
+    }
+
+    /// Reads a polygon in the Osmosis polygon filter file format.
+    pub fn from_poly_file<P: AsRef<Path>>(path: P) -> Result<Area, AreaError> {
+        let content = std::fs::read_to_string(path).map_err(AreaError::Io)?;
+        Area::parse_poly(&content)
+    }
+
+    fn parse_poly(content: &str) -> Result<Area, AreaError> {
+        let mut lines = content
+            .lines()
+            .enumerate()
+            .map(|(i, l)| (i + 1, l.trim()))
+            .filter(|(_, l)| !l.is_empty());
+        // the first line holds the name of the polygon
+        lines.next();
+
+        let mut rings = Vec::new();
+        let mut current: Option<Ring> = None;
+        for (number, line) in lines {
+            let invalid = |msg: &str| AreaError::InvalidPolygon(number, msg.to_owned());
+            match current.take() {
+                None if line == "END" => {
+                    if rings.is_empty() {
+                        return Err(invalid("polygon without rings"));
+                    }
+                    return Ok(Area::Polygon(rings));
+                }
+                None => {
+                    current = Some(Ring {
+                        points: Vec::new(),
+                        hole: line.starts_with('!'),
+                    })
+                }
+                Some(ring) if line == "END" => {
+                    if ring.points.len() < 3 {
+                        return Err(invalid("ring with less than three points"));
+                    }
+                    rings.push(ring);
+                }
+                Some(mut ring) => {
+                    let coords: Vec<f64> = line
+                        .split_whitespace()
+                        .map(str::parse)
+                        .collect::<Result<_, _>>()
+                        .map_err(|_| invalid("expected two coordinates"))?;
+                    if coords.len() != 2 {
+                        return Err(invalid("expected two coordinates"));
+                    }
+                    ring.points.push((coords[0], coords[1]));
+                    current = Some(ring);
+                }
+            }
+        }
+        Err(AreaError::InvalidPolygon(
+            content.lines().count(),
+            "missing END".to_owned(),
+        ))
+    }
+}
+
+impl FromStr for Area {
+    type Err = AreaError;
+
+    /// Parses a bounding box given as `minlon,minlat,maxlon,maxlat`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || AreaError::InvalidBoundingBox(s.to_owned());
+        let values: Vec<f64> = s
+            .split(',')
+            .map(|v| v.trim().parse())
+            .collect::<Result<_, _>>()
+            .map_err(|_| invalid())?;
+        match values[..] {
+            [min_lng, min_lat, max_lng, max_lat] if min_lng <= max_lng && min_lat <= max_lat => {
+                Ok(Area::BoundingBox {
+                    min_lng,
+                    min_lat,
+                    max_lng,
+                    max_lat,
+                })
+            }
+            _ => Err(invalid()),
+        }
+    }
+}
+
+#[test]
+fn bounding_box() {
+    let area: Area = "8.9,47.9,9.1,48.1".parse().unwrap();
+    assert!(area.contains(48.0, 9.0));
+    assert!(!area.contains(48.2, 9.0));
+    assert!(!area.contains(48.0, 8.8));
+    assert!("9.1,47.9,8.9,48.1".parse::<Area>().is_err());
+    assert!("9.1,47.9".parse::<Area>().is_err());
+}
+
+#[test]
+fn polygon_with_hole() {
+    let poly = "city
+1
+    9.0 48.0
+    10.0 48.0
+    10.0 49.0
+    9.0 49.0
+END
+!2
+    9.4 48.4
+    9.6 48.4
+    9.6 48.6
+    9.4 48.6
+END
+END
+";
+    let area = Area::parse_poly(poly).unwrap();
+    assert!(area.contains(48.2, 9.2));
+    assert!(!area.contains(48.5, 9.5));
+    assert!(!area.contains(47.5, 9.5));
+    assert!(Area::parse_poly("city\n1\n 9.0 48.0\nEND\nEND\n").is_err());
+}
@@ -0,0 +1,192 @@
+/*
+Pbfextractor creates graph files for the cycle-routing projects from pbf and srtm data
+Copyright (C) 2018  Florian Barth
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! Compact binary graph format. All values are little endian.
+//!
+//! ```text
+//! header: magic "PBFG", version: u32, metric count: u32,
+//!         per metric: name length: u32, name: utf-8 bytes,
+//!         node count: u64, edge count: u64
+//! node:   osm id: u64, lat: f64, long: f64, height: f64
+//! edge:   source: u64, dest: u64, one f64 per metric
+//! ```
+//!
+//! Nodes are numbered by their position in the file. Unlike the text
+//! format, costs are not rounded.
+
+use super::pbf::{Edge, InternalMetrics, MetricIndices, Node};
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use std::io::{Error, ErrorKind, Read, Result, Write};
+
+pub const MAGIC: &[u8; 4] = b"PBFG";
+pub const VERSION: u32 = 1;
+
+/// Upper bound for preallocating vectors from counts in the header, so a
+/// corrupt header fails with an error once the data ends instead of
+/// aborting on a huge allocation.
+const MAX_PREALLOCATION: usize = 1 << 16;
+
+pub fn write_binary_graph<W: Write>(
+    graph: &mut W,
+    nodes: &[Node],
+    edges: &[Edge],
+    indices: &MetricIndices,
+    internal: &InternalMetrics,
+) -> Result<()> {
+    let metrics: Vec<&String> = indices.keys().filter(|m| !internal.contains(*m)).collect();
+
+    graph.write_all(MAGIC)?;
+    graph.write_u32::<LittleEndian>(VERSION)?;
+    graph.write_u32::<LittleEndian>(metrics.len() as u32)?;
+    for metric in metrics {
+        graph.write_u32::<LittleEndian>(metric.len() as u32)?;
+        graph.write_all(metric.as_bytes())?;
+    }
+    graph.write_u64::<LittleEndian>(nodes.len() as u64)?;
+    graph.write_u64::<LittleEndian>(edges.len() as u64)?;
+
+    for node in nodes {
+        graph.write_u64::<LittleEndian>(node.osm_id as u64)?;
+        graph.write_f64::<LittleEndian>(node.lat)?;
+        graph.write_f64::<LittleEndian>(node.long)?;
+        graph.write_f64::<LittleEndian>(node.height)?;
+    }
+    for edge in edges {
+        graph.write_u64::<LittleEndian>(edge.source as u64)?;
+        graph.write_u64::<LittleEndian>(edge.dest as u64)?;
+        for cost in edge.costs(indices, internal) {
+            graph.write_f64::<LittleEndian>(cost)?;
+        }
+    }
+    graph.flush()
+}
+
+#[allow(dead_code)]
+pub struct BinaryGraph {
+    pub metrics: Vec<String>,
+    pub nodes: Vec<Node>,
+    pub edges: Vec<Edge>,
+}
+
+#[allow(dead_code)]
+pub fn read_binary_graph<R: Read>(graph: &mut R) -> Result<BinaryGraph> {
+    let invalid = |msg: String| Error::new(ErrorKind::InvalidData, msg);
+
+    let mut magic = [0; 4];
+    graph.read_exact(&mut magic)?;
+    if &magic != MAGIC {
+        return Err(invalid("not a binary graph file".to_owned()));
+    }
+    let version = graph.read_u32::<LittleEndian>()?;
+    if version != VERSION {
+        return Err(invalid(format!("unsupported graph version {}", version)));
+    }
+
+    let metric_count = graph.read_u32::<LittleEndian>()? as usize;
+    let mut metrics = Vec::with_capacity(metric_count.min(MAX_PREALLOCATION));
+    for _ in 0..metric_count {
+        let len = graph.read_u32::<LittleEndian>()? as u64;
+        let mut name = Vec::new();
+        if graph.by_ref().take(len).read_to_end(&mut name)? as u64 != len {
+            return Err(Error::from(ErrorKind::UnexpectedEof));
+        }
+        metrics.push(String::from_utf8(name).map_err(|e| invalid(e.to_string()))?);
+    }
+    let node_count = graph.read_u64::<LittleEndian>()? as usize;
+    let edge_count = graph.read_u64::<LittleEndian>()? as usize;
+
+    let mut nodes = Vec::with_capacity(node_count.min(MAX_PREALLOCATION));
+    for _ in 0..node_count {
+        let osm_id = graph.read_u64::<LittleEndian>()? as usize;
+        let lat = graph.read_f64::<LittleEndian>()?;
+        let long = graph.read_f64::<LittleEndian>()?;
+        let height = graph.read_f64::<LittleEndian>()?;
+        nodes.push(Node::new(osm_id, lat, long, height));
+    }
+    let mut edges = Vec::with_capacity(edge_count.min(MAX_PREALLOCATION));
+    for _ in 0..edge_count {
+        let source = graph.read_u64::<LittleEndian>()? as usize;
+        let dest = graph.read_u64::<LittleEndian>()? as usize;
+        let mut costs = Vec::with_capacity(metric_count.min(MAX_PREALLOCATION));
+        for _ in 0..metric_count {
+            costs.push(graph.read_f64::<LittleEndian>()?);
+        }
+        edges.push(Edge::with_costs(source, dest, costs));
+    }
+
+    Ok(BinaryGraph {
+        metrics,
+        nodes,
+        edges,
+    })
+}
+
+#[test]
+fn binary_round_trip() {
+    let indices: MetricIndices = vec![
+        ("Distance".to_owned(), 0),
+        ("HeightAscent".to_owned(), 1),
+        ("CarSpeed".to_owned(), 2),
+    ]
+    .into_iter()
+    .collect();
+    let internal: InternalMetrics = vec!["CarSpeed".to_owned()].into_iter().collect();
+    let nodes = vec![
+        Node::new(42, 48.7, 9.1, 250.5),
+        Node::new(7, -33.9, 151.2, 12.0),
+    ];
+    let edges = vec![
+        Edge::with_costs(0, 1, vec![1234.5, 3.25, 50.0]),
+        Edge::with_costs(1, 0, vec![1234.5, 0.0, 50.0]),
+    ];
+
+    let mut buffer = Vec::new();
+    write_binary_graph(&mut buffer, &nodes, &edges, &indices, &internal).unwrap();
+    let graph = read_binary_graph(&mut buffer.as_slice()).unwrap();
+
+    assert_eq!(vec!["Distance", "HeightAscent"], graph.metrics);
+    assert_eq!(nodes, graph.nodes);
+    assert!(graph.edges[0] == Edge::with_costs(0, 1, vec![1234.5, 3.25]));
+    assert!(graph.edges[1] == Edge::with_costs(1, 0, vec![1234.5, 0.0]));
+
+    buffer[0] = b'X';
+    assert!(read_binary_graph(&mut buffer.as_slice()).is_err());
+}
+
+#[test]
+fn corrupt_header() {
+    let header = |metric_count: u32, name_len: u32, node_count: u64| {
+        let mut buffer = Vec::new();
+        buffer.extend_from_slice(MAGIC);
+        buffer.write_u32::<LittleEndian>(VERSION).unwrap();
+        buffer.write_u32::<LittleEndian>(metric_count).unwrap();
+        buffer.write_u32::<LittleEndian>(name_len).unwrap();
+        buffer.extend_from_slice(b"Distance");
+        buffer.write_u64::<LittleEndian>(node_count).unwrap();
+        buffer.write_u64::<LittleEndian>(u64::MAX).unwrap();
+        buffer
+    };
+    let read = |buffer: Vec<u8>| read_binary_graph(&mut buffer.as_slice()).map(|_| ());
+
+    assert!(read(header(u32::MAX, 8, 0)).is_err());
+    assert!(read(header(1, u32::MAX, 0)).is_err());
+    assert!(read(header(1, 8, u64::MAX)).is_err());
+    assert!(read(header(1, 8, 0)).is_err());
+}
@@ -0,0 +1,194 @@
+/*
+Pbfextractor creates graph files for the cycle-routing projects from pbf and srtm data
+Copyright (C) 2018  Florian Barth
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use super::pbf::{Edge, Node, NodeId};
+
+/// Which strongly connected components are kept in the graph.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum ComponentFilter {
+    #[default]
+    All,
+    Largest,
+    /// Drop components with fewer nodes than this.
+    MinSize(usize),
+}
+
+/// Calculates the strongly connected components with Tarjan's algorithm.
+/// Returns the component of every node, numbered from 0.
+pub fn strongly_connected_components(node_count: usize, edges: &[Edge]) -> Vec<usize> {
+    const UNVISITED: usize = usize::MAX;
+
+    let mut first_out = vec![0; node_count + 1];
+    for e in edges {
+        first_out[e.source + 1] += 1;
+    }
+    for i in 0..node_count {
+        first_out[i + 1] += first_out[i];
+    }
+    let mut targets = vec![0; edges.len()];
+    let mut next = first_out.clone();
+    for e in edges {
+        targets[next[e.source]] = e.dest;
+        next[e.source] += 1;
+    }
+
+    let mut index = vec![UNVISITED; node_count];
+    let mut low_link = vec![0; node_count];
+    let mut on_stack = vec![false; node_count];
+    let mut component = vec![UNVISITED; node_count];
+    let mut stack = Vec::new();
+    let mut next_index = 0;
+    let mut next_component = 0;
+
+    for root in 0..node_count {
+        if index[root] != UNVISITED {
+            continue;
+        }
+        // (node, position of the next outgoing edge to look at)
+        let mut call_stack = vec![(root, first_out[root])];
+        index[root] = next_index;
+        low_link[root] = next_index;
+        next_index += 1;
+        stack.push(root);
+        on_stack[root] = true;
+
+        while let Some(&mut (node, ref mut edge)) = call_stack.last_mut() {
+            if *edge < first_out[node + 1] {
+                let target = targets[*edge];
+                *edge += 1;
+                if index[target] == UNVISITED {
+                    index[target] = next_index;
+                    low_link[target] = next_index;
+                    next_index += 1;
+                    stack.push(target);
+                    on_stack[target] = true;
+                    call_stack.push((target, first_out[target]));
+                } else if on_stack[target] {
+                    low_link[node] = low_link[node].min(index[target]);
+                }
+                continue;
+            }
+
+            call_stack.pop();
+            if let Some(&(parent, _)) = call_stack.last() {
+                low_link[parent] = low_link[parent].min(low_link[node]);
+            }
+            if low_link[node] == index[node] {
+                loop {
+                    let member = stack.pop().expect("node is on the stack");
+                    on_stack[member] = false;
+                    component[member] = next_component;
+                    if member == node {
+                        break;
+                    }
+                }
+                next_component += 1;
+            }
+        }
+    }
+    component
+}
+
+/// Decides for every node whether it is part of a component kept by `filter`.
+pub fn nodes_to_keep(node_count: usize, edges: &[Edge], filter: ComponentFilter) -> Vec<bool> {
+    if filter == ComponentFilter::All {
+        return vec![true; node_count];
+    }
+    let component = strongly_connected_components(node_count, edges);
+    let component_count = component.iter().max().map(|c| c + 1).unwrap_or(0);
+    let mut sizes = vec![0; component_count];
+    for c in &component {
+        sizes[*c] += 1;
+    }
+
+    let keep_component: Vec<bool> = match filter {
+        ComponentFilter::All => unreachable!(),
+        ComponentFilter::Largest => {
+            let largest = (0..component_count).max_by_key(|c| sizes[*c]);
+            (0..component_count).map(|c| Some(c) == largest).collect()
+        }
+        ComponentFilter::MinSize(min) => sizes.iter().map(|s| *s >= min).collect(),
+    };
+    component.iter().map(|c| keep_component[*c]).collect()
+}
+
+/// Removes all nodes not marked in `keep` and all edges touching them. The
+/// remaining nodes are renumbered so the ids stay dense.
+pub fn retain_nodes(nodes: Vec<Node>, edges: Vec<Edge>, keep: &[bool]) -> (Vec<Node>, Vec<Edge>) {
+    let mut new_ids = vec![None; nodes.len()];
+    let mut next: NodeId = 0;
+    for (id, k) in keep.iter().enumerate() {
+        if *k {
+            new_ids[id] = Some(next);
+            next += 1;
+        }
+    }
+
+    let nodes = nodes
+        .into_iter()
+        .zip(keep)
+        .filter(|(_, k)| **k)
+        .map(|(n, _)| n)
+        .collect();
+    let edges = edges
+        .into_iter()
+        .filter_map(|mut e| {
+            e.source = new_ids[e.source]?;
+            e.dest = new_ids[e.dest]?;
+            Some(e)
+        })
+        .collect();
+    (nodes, edges)
+}
+
+#[cfg(test)]
+fn edges(list: &[(NodeId, NodeId)]) -> Vec<Edge> {
+    list.iter()
+        .map(|(s, d)| Edge::with_costs(*s, *d, vec![]))
+        .collect()
+}
+
+#[test]
+fn tarjan_finds_components() {
+    // 0 <-> 1 -> 2 <-> 3 <-> 4, 5 alone
+    let e = edges(&[(0, 1), (1, 0), (1, 2), (2, 3), (3, 2), (3, 4), (4, 3)]);
+    let c = strongly_connected_components(6, &e);
+    assert_eq!(c[0], c[1]);
+    assert_eq!(c[2], c[3]);
+    assert_eq!(c[3], c[4]);
+    assert_ne!(c[1], c[2]);
+    assert_ne!(c[5], c[0]);
+    assert_ne!(c[5], c[2]);
+
+    let keep = nodes_to_keep(6, &e, ComponentFilter::Largest);
+    assert_eq!(vec![false, false, true, true, true, false], keep);
+    let keep = nodes_to_keep(6, &e, ComponentFilter::MinSize(2));
+    assert_eq!(vec![true, true, true, true, true, false], keep);
+}
+
+#[test]
+fn retain_nodes_compacts_ids() {
+    let nodes: Vec<Node> = (0..4).map(|i| Node::new(i, 0.0, 0.0, 0.0)).collect();
+    let e = edges(&[(0, 1), (1, 3), (3, 1)]);
+    let (nodes, e) = retain_nodes(nodes, e, &[false, true, false, true]);
+
+    assert_eq!(
+        vec![1, 3],
+        nodes.iter().map(|n| n.osm_id).collect::<Vec<_>>()
+    );
+    assert!(e == edges(&[(0, 1), (1, 0)]));
+}
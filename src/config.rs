@@ -0,0 +1,489 @@
+/*
+Pbfextractor creates graph files for the cycle-routing projects from pbf and srtm data
+Copyright (C) 2018  Florian Barth
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use super::access::{ModeChain, BICYCLE, CAR, FOOT, HGV};
+use super::dimensions::VehicleDimensions;
+use super::metrics::*;
+use super::pbf::{CostMetrics, InternalMetrics, NodeMetrics, TagMetrics};
+use super::script::{ScriptKind, ScriptedMetric};
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::rc::Rc;
+use std::str::FromStr;
+
+#[derive(Debug)]
+pub enum ConfigError {
+    UnknownProfile(String),
+    UnknownMetric(String),
+    MalformedMetric(String),
+    DuplicateMetric(String),
+    MissingDependency {
+        metric: String,
+        dependency: String,
+    },
+    DependencyCycle(String),
+    /// A dependency of the wrong kind, e.g. a speed where a distance is expected.
+    DependencyKind {
+        metric: String,
+        dependency: String,
+        expected: &'static str,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::UnknownProfile(p) => write!(
+                f,
+                "unknown profile '{}', expected one of: car, bicycle, truck, foot",
+                p
+            ),
+            ConfigError::UnknownMetric(m) => write!(f, "unknown metric '{}'", m),
+            ConfigError::MalformedMetric(m) => write!(
+                f,
+                "malformed metric '{}', expected 'Name' or 'Name:Dependency/Dependency'",
+                m
+            ),
+            ConfigError::DuplicateMetric(m) => write!(f, "metric '{}' is selected twice", m),
+            ConfigError::MissingDependency { metric, dependency } => write!(
+                f,
+                "metric '{}' depends on '{}', which is not selected \
+                 (add it with --metric or --internal-metric)",
+                metric, dependency
+            ),
+            ConfigError::DependencyCycle(m) => {
+                write!(f, "metric '{}' depends on itself", m)
+            }
+            ConfigError::DependencyKind {
+                metric,
+                dependency,
+                expected,
+            } => write!(
+                f,
+                "metric '{}' expects {} instead of '{}'",
+                metric, expected, dependency
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// The vehicle the graph is extracted for. It determines which ways are
+/// part of the graph.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VehicleProfile {
+    Car,
+    Bicycle,
+    Truck,
+    Foot,
+}
+
+impl VehicleProfile {
+    /// The access keys of the transport mode of the profile.
+    pub fn modes(self) -> ModeChain {
+        match self {
+            VehicleProfile::Car => CAR,
+            VehicleProfile::Truck => HGV,
+            VehicleProfile::Bicycle => BICYCLE,
+            VehicleProfile::Foot => FOOT,
+        }
+    }
+
+    /// The edge filter of the profile, `dimensions` restrict the ways of
+    /// trucks.
+    pub fn edge_filter(self, dimensions: VehicleDimensions) -> Box<dyn EdgeFilter> {
+        match self {
+            VehicleProfile::Car => Box::new(CarEdgeFilter),
+            VehicleProfile::Truck => Box::new(TruckEdgeFilter { dimensions }),
+            VehicleProfile::Bicycle => Box::new(BicycleEdgeFilter),
+            VehicleProfile::Foot => Box::new(FootEdgeFilter),
+        }
+    }
+}
+
+impl FromStr for VehicleProfile {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "car" => Ok(VehicleProfile::Car),
+            "bicycle" => Ok(VehicleProfile::Bicycle),
+            "truck" => Ok(VehicleProfile::Truck),
+            "foot" => Ok(VehicleProfile::Foot),
+            _ => Err(ConfigError::UnknownProfile(s.to_owned())),
+        }
+    }
+}
+
+/// A metric as given on the command line, e.g. `Distance` or
+/// `TravelTime:Distance/CarSpeed`.
+#[derive(Debug, PartialEq)]
+struct MetricSpec {
+    name: String,
+    dependencies: Vec<String>,
+}
+
+impl FromStr for MetricSpec {
+    type Err = ConfigError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let malformed = || ConfigError::MalformedMetric(s.to_owned());
+        let (name, dependencies) = match s.split_once(':') {
+            Some((name, deps)) => {
+                let deps: Vec<String> = deps.split('/').map(|d| d.trim().to_owned()).collect();
+                if deps.iter().any(String::is_empty) {
+                    return Err(malformed());
+                }
+                (name.trim(), deps)
+            }
+            None => (s.trim(), Vec::new()),
+        };
+        if name.is_empty() {
+            return Err(malformed());
+        }
+        Ok(MetricSpec {
+            name: name.to_owned(),
+            dependencies,
+        })
+    }
+}
+
+/// A metric of any kind, used to hand metrics defined outside of this
+/// module, e.g. by a profile file, to `MetricSelection::from_specs`.
+#[derive(Clone)]
+pub enum AnyMetric {
+    Tag(Rc<dyn TagMetric<f64>>),
+    Node(Rc<dyn NodeMetric<f64>>),
+    Cost(Rc<dyn CostMetric<f64>>),
+}
+
+impl AnyMetric {
+    fn as_metric(&self) -> Rc<dyn Metric> {
+        match self {
+            AnyMetric::Tag(t) => t.clone(),
+            AnyMetric::Node(n) => n.clone(),
+            AnyMetric::Cost(c) => c.clone(),
+        }
+    }
+}
+
+impl From<ScriptedMetric> for AnyMetric {
+    fn from(script: ScriptedMetric) -> AnyMetric {
+        match script.kind() {
+            ScriptKind::Tag => AnyMetric::Tag(Rc::new(script)),
+            ScriptKind::Node => AnyMetric::Node(Rc::new(script)),
+            ScriptKind::Cost => AnyMetric::Cost(Rc::new(script)),
+        }
+    }
+}
+
+/// The metrics selected for a graph, split up the way the `Loader` expects them.
+#[derive(Default)]
+pub struct MetricSelection {
+    pub tag_metrics: TagMetrics,
+    pub node_metrics: NodeMetrics,
+    pub cost_metrics: CostMetrics,
+    pub internal_metrics: InternalMetrics,
+}
+
+impl MetricSelection {
+    /// Builds all metrics named in `exported` and `internal`. Metrics in
+    /// `internal` are calculated but not written to the graph file.
+    /// Metrics in `custom` take precedence over built-in metrics of the
+    /// same name.
+    pub fn from_specs<S: AsRef<str>>(
+        exported: &[S],
+        internal: &[S],
+        custom: &[AnyMetric],
+        grid: &Rc<RefCell<Grid>>,
+    ) -> Result<MetricSelection, ConfigError> {
+        let mut specs = Vec::new();
+        for s in exported {
+            specs.push((s.as_ref().parse::<MetricSpec>()?, false));
+        }
+        for s in internal {
+            specs.push((s.as_ref().parse::<MetricSpec>()?, true));
+        }
+
+        let mut selection = MetricSelection::default();
+        let mut built: BTreeMap<String, AnyMetric> = BTreeMap::new();
+
+        // Cost metrics are built last, so they can depend on every tag and
+        // node metric regardless of the order given on the command line.
+        let (simple, cost): (Vec<_>, Vec<_>) = specs
+            .into_iter()
+            .partition(|(spec, _)| spec.dependencies.is_empty());
+
+        for (spec, is_internal) in simple.iter().chain(cost.iter()) {
+            let metric = build_metric(spec, custom, grid, &built)?;
+            let name = metric.as_metric().name();
+            if built.contains_key(&name) {
+                return Err(ConfigError::DuplicateMetric(name));
+            }
+            built.insert(name.clone(), metric.clone());
+            if *is_internal {
+                selection.internal_metrics.insert(name);
+            }
+            match metric {
+                AnyMetric::Tag(t) => selection.tag_metrics.push(t),
+                AnyMetric::Node(n) => selection.node_metrics.push(n),
+                AnyMetric::Cost(c) => selection.cost_metrics.push(c),
+            }
+        }
+
+        for metric in built.values().map(AnyMetric::as_metric) {
+            for dependency in metric.dependencies() {
+                if !built.contains_key(&dependency) {
+                    return Err(ConfigError::MissingDependency {
+                        metric: metric.name(),
+                        dependency,
+                    });
+                }
+            }
+        }
+        selection.cost_metrics = order_cost_metrics(selection.cost_metrics)?;
+        Ok(selection)
+    }
+
+    /// The name of the first selected metric that needs node heights.
+    pub fn height_metric(&self) -> Option<String> {
+        self.node_metrics
+            .iter()
+            .find(|n| n.uses_height())
+            .map(|n| n.name())
+    }
+}
+
+/// Orders cost metrics so that every cost metric is calculated after the
+/// cost metrics it depends on.
+fn order_cost_metrics(mut pending: CostMetrics) -> Result<CostMetrics, ConfigError> {
+    let cost_names: HashSet<String> = pending.iter().map(|c| c.name()).collect();
+    let mut done = HashSet::new();
+    let mut ordered = Vec::new();
+    while !pending.is_empty() {
+        let (ready, rest): (CostMetrics, CostMetrics) = pending.into_iter().partition(|c| {
+            c.dependencies()
+                .iter()
+                .all(|d| !cost_names.contains(d) || done.contains(d))
+        });
+        if ready.is_empty() {
+            return Err(ConfigError::DependencyCycle(rest[0].name()));
+        }
+        done.extend(ready.iter().map(|c| c.name()));
+        ordered.extend(ready);
+        pending = rest;
+    }
+    Ok(ordered)
+}
+
+fn build_metric(
+    spec: &MetricSpec,
+    custom: &[AnyMetric],
+    grid: &Rc<RefCell<Grid>>,
+    built: &BTreeMap<String, AnyMetric>,
+) -> Result<AnyMetric, ConfigError> {
+    // Both cost metrics combine a distance-like node metric as their first
+    // dependency with a tag metric, such as a speed or an unsuitability.
+    let dependency = |i: usize| -> Result<Rc<dyn Metric>, ConfigError> {
+        let name = &spec.dependencies[i];
+        let expected = if i == 0 {
+            "a distance node metric first"
+        } else {
+            "a speed or unsuitability tag metric second"
+        };
+        match (i, built.get(name)) {
+            (0, Some(AnyMetric::Node(n))) => Ok(n.clone()),
+            (1, Some(AnyMetric::Tag(t))) => Ok(t.clone()),
+            (_, Some(_)) => Err(ConfigError::DependencyKind {
+                metric: spec.name.clone(),
+                dependency: name.clone(),
+                expected,
+            }),
+            (_, None) => Err(ConfigError::MissingDependency {
+                metric: spec.name.clone(),
+                dependency: name.clone(),
+            }),
+        }
+    };
+    let expect_dependencies = |count: usize| -> Result<(), ConfigError> {
+        if spec.dependencies.len() == count {
+            Ok(())
+        } else {
+            Err(ConfigError::MalformedMetric(spec.name.clone()))
+        }
+    };
+
+    let metric = match spec.name.as_str() {
+        "TravelTime" => {
+            expect_dependencies(2)?;
+            AnyMetric::Cost(Rc::new(TravelTime::new(dependency(0)?, dependency(1)?)))
+        }
+        "UnsuitDistMetric" => {
+            expect_dependencies(2)?;
+            AnyMetric::Cost(Rc::new(UnsuitDistMetric::new(
+                dependency(0)?,
+                dependency(1)?,
+            )))
+        }
+        name => {
+            expect_dependencies(0)?;
+            if let Some(m) = custom.iter().find(|m| m.as_metric().name() == name) {
+                return Ok(m.clone());
+            }
+            match name {
+                "CarSpeed" => AnyMetric::Tag(Rc::new(CarSpeed)),
+                "TruckSpeed" => AnyMetric::Tag(Rc::new(TruckSpeed)),
+                "FastCarSpeed" => AnyMetric::Tag(Rc::new(FastCarSpeed)),
+                "BicycleUnsuitability" => AnyMetric::Tag(Rc::new(BicycleUnsuitability)),
+                "BicycleSpeed" => AnyMetric::Tag(Rc::new(BicycleSpeed)),
+                "SurfaceQuality" => AnyMetric::Tag(Rc::new(SurfaceQuality)),
+                "CarDestinationAccess" => AnyMetric::Tag(Rc::new(DestinationAccess::CAR)),
+                "TruckDestinationAccess" => AnyMetric::Tag(Rc::new(DestinationAccess::TRUCK)),
+                "BicycleDestinationAccess" => AnyMetric::Tag(Rc::new(DestinationAccess::BICYCLE)),
+                "FootDestinationAccess" => AnyMetric::Tag(Rc::new(DestinationAccess::FOOT)),
+                "WalkingSpeed" => AnyMetric::Tag(Rc::new(WalkingSpeed)),
+                "PedestrianUnsuitability" => AnyMetric::Tag(Rc::new(PedestrianUnsuitability)),
+                "EdgeCount" => AnyMetric::Tag(Rc::new(EdgeCount)),
+                "RandomWeights" => AnyMetric::Tag(Rc::new(RandomWeights)),
+                "Distance" => AnyMetric::Node(Rc::new(Distance)),
+                "HeightAscent" => AnyMetric::Node(Rc::new(HeightAscent)),
+                "HeightDescent" => AnyMetric::Node(Rc::new(HeightDescent)),
+                "Gradient" => AnyMetric::Node(Rc::new(Gradient)),
+                "MaxGradient" => AnyMetric::Node(Rc::new(MaxGradient)),
+                "ClimbPenalty" => AnyMetric::Node(Rc::new(ClimbPenalty::default())),
+                "GridX" => AnyMetric::Node(Rc::new(GridX(grid.clone()))),
+                "GridY" => AnyMetric::Node(Rc::new(GridY(grid.clone()))),
+                "ChessBoard" => AnyMetric::Node(Rc::new(ChessBoard(grid.clone()))),
+                _ => return Err(ConfigError::UnknownMetric(name.to_owned())),
+            }
+        }
+    };
+    Ok(metric)
+}
+
+#[test]
+fn parse_metric_spec() {
+    let spec: MetricSpec = "TravelTime: Distance / CarSpeed".parse().unwrap();
+    assert_eq!("TravelTime", spec.name);
+    assert_eq!(vec!["Distance", "CarSpeed"], spec.dependencies);
+
+    let spec: MetricSpec = "Distance".parse().unwrap();
+    assert!(spec.dependencies.is_empty());
+
+    assert!("TravelTime:Distance/".parse::<MetricSpec>().is_err());
+}
+
+#[test]
+fn metric_selection_resolves_dependencies() {
+    let grid = Grid::new_ptr();
+    let selection = MetricSelection::from_specs(
+        &["TravelTime:Distance/CarSpeed", "Distance"],
+        &["CarSpeed"],
+        &[],
+        &grid,
+    )
+    .unwrap();
+
+    assert_eq!(1, selection.tag_metrics.len());
+    assert_eq!(1, selection.node_metrics.len());
+    assert_eq!(1, selection.cost_metrics.len());
+    assert_eq!(
+        "TravelTime: Distance / CarSpeed",
+        selection.cost_metrics[0].name()
+    );
+    assert!(selection.internal_metrics.contains("CarSpeed"));
+
+    let missing = MetricSelection::from_specs(&["TravelTime:Distance/CarSpeed"], &[], &[], &grid);
+    assert!(matches!(
+        missing,
+        Err(ConfigError::MissingDependency { .. })
+    ));
+    let swapped = MetricSelection::from_specs(
+        &["TravelTime:CarSpeed/Distance", "Distance", "CarSpeed"],
+        &[],
+        &[],
+        &grid,
+    );
+    assert!(matches!(
+        swapped,
+        Err(ConfigError::DependencyKind { ref metric, .. }) if metric == "TravelTime"
+    ));
+    let unsuitability = MetricSelection::from_specs(
+        &[
+            "UnsuitDistMetric:Distance/HeightAscent",
+            "Distance",
+            "HeightAscent",
+        ],
+        &[],
+        &[],
+        &grid,
+    );
+    assert!(matches!(
+        unsuitability,
+        Err(ConfigError::DependencyKind { .. })
+    ));
+    let unknown = MetricSelection::from_specs(&["Speed"], &[], &[], &grid);
+    assert!(matches!(unknown, Err(ConfigError::UnknownMetric(_))));
+}
+
+#[test]
+fn scripted_cost_metrics_are_ordered() {
+    let grid = Grid::new_ptr();
+    let custom: Vec<AnyMetric> = [
+        "Double:cost:2 * cost(\"Weighted\")",
+        "Weighted:cost:cost(\"Distance\") * cost(\"EdgeCount\")",
+    ]
+    .iter()
+    .map(|s| s.parse::<ScriptedMetric>().unwrap().into())
+    .collect();
+
+    let selection = MetricSelection::from_specs(
+        &["Double", "Weighted", "Distance", "EdgeCount"],
+        &[],
+        &custom,
+        &grid,
+    )
+    .unwrap();
+    let order: Vec<String> = selection.cost_metrics.iter().map(|c| c.name()).collect();
+    assert_eq!(vec!["Weighted", "Double"], order);
+
+    let missing = MetricSelection::from_specs(&["Double", "Weighted"], &[], &custom, &grid);
+    assert!(matches!(
+        missing,
+        Err(ConfigError::MissingDependency { .. })
+    ));
+}
+
+#[test]
+fn height_metrics_are_detected() {
+    let grid = Grid::new_ptr();
+    let custom: Vec<AnyMetric> = ["Climb:node:max(target.height - source.height, 0)"]
+        .iter()
+        .map(|s| s.parse::<ScriptedMetric>().unwrap().into())
+        .collect();
+
+    let selection = MetricSelection::from_specs(&["Distance"], &[], &custom, &grid).unwrap();
+    assert_eq!(None, selection.height_metric());
+    let selection =
+        MetricSelection::from_specs(&["Distance", "HeightAscent"], &[], &custom, &grid).unwrap();
+    assert_eq!(Some("HeightAscent".to_owned()), selection.height_metric());
+    let selection = MetricSelection::from_specs(&["Climb"], &[], &custom, &grid).unwrap();
+    assert_eq!(Some("Climb".to_owned()), selection.height_metric());
+}
@@ -0,0 +1,202 @@
+/*
+Pbfextractor creates graph files for the cycle-routing projects from pbf and srtm data
+Copyright (C) 2018  Florian Barth
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use super::components::retain_nodes;
+use super::metrics::{Aggregation, Distance, NodeMetric};
+use super::pbf::{Edge, Node, NodeId};
+
+use std::collections::HashSet;
+use std::io::{Result, Write};
+
+/// Merges chains of degree-2 nodes into single edges.
+///
+/// A node is part of a chain if it connects exactly two other nodes, either
+/// with one edge in and one edge out or with edges in both directions to
+/// both of them. Nodes in `fixed` are never removed. With `same_way` only
+/// edges created from the same OSM way are merged. The costs of merged edges
+/// are combined with `aggregations`, indexed like the edge costs.
+pub fn contract_chains(
+    nodes: Vec<Node>,
+    edges: Vec<Edge>,
+    aggregations: &[Aggregation],
+    fixed: &HashSet<NodeId>,
+    same_way: bool,
+    keep_geometry: bool,
+) -> (Vec<Node>, Vec<Edge>) {
+    let mut incoming = vec![Vec::new(); nodes.len()];
+    let mut outgoing = vec![Vec::new(); nodes.len()];
+    for (i, e) in edges.iter().enumerate() {
+        outgoing[e.source].push(i);
+        incoming[e.dest].push(i);
+    }
+    let is_chain: Vec<bool> = (0..nodes.len())
+        .map(|v| {
+            !fixed.contains(&v) && is_chain_node(v, &edges, &incoming[v], &outgoing[v], same_way)
+        })
+        .collect();
+
+    let mut removed = vec![false; nodes.len()];
+    let mut paths = Vec::new();
+    for (i, e) in edges.iter().enumerate() {
+        if is_chain[e.source] {
+            continue;
+        }
+        let mut path = vec![i];
+        let mut current = e;
+        while is_chain[current.dest] && path.len() <= edges.len() {
+            let v = current.dest;
+            let next = outgoing[v]
+                .iter()
+                .copied()
+                .find(|o| edges[*o].dest != current.source)
+                .expect("chain nodes have an edge leading on");
+            removed[v] = true;
+            path.push(next);
+            current = &edges[next];
+        }
+        paths.push(path);
+    }
+    // chains without any other node, e.g. isolated circles, stay unchanged
+    for (i, e) in edges.iter().enumerate() {
+        if is_chain[e.source] && !removed[e.source] {
+            paths.push(vec![i]);
+        }
+    }
+
+    let mut contracted = Vec::with_capacity(paths.len());
+    for path in paths {
+        let path: Vec<&Edge> = path.iter().map(|i| &edges[*i]).collect();
+        let lengths: Vec<f64> = path
+            .iter()
+            .map(|e| {
+                NodeMetric::<f64>::calc(&Distance, &nodes[e.source], &nodes[e.dest]).unwrap_or(0.0)
+            })
+            .collect();
+        let mut merged = Edge::merge(&path, &lengths, aggregations);
+        if merged.source == merged.dest {
+            continue;
+        }
+        if keep_geometry {
+            for (i, e) in path.iter().enumerate() {
+                merged.geometry.extend(&e.geometry);
+                if i + 1 < path.len() {
+                    let n = &nodes[e.dest];
+                    merged.geometry.push((n.lat, n.long));
+                }
+            }
+        }
+        contracted.push(merged);
+    }
+
+    let keep: Vec<bool> = removed.iter().map(|r| !r).collect();
+    retain_nodes(nodes, contracted, &keep)
+}
+
+fn is_chain_node(
+    v: NodeId,
+    edges: &[Edge],
+    incoming: &[usize],
+    outgoing: &[usize],
+    same_way: bool,
+) -> bool {
+    if same_way {
+        let mut ways = incoming.iter().chain(outgoing).map(|e| edges[*e].osm_way);
+        let first = ways.next();
+        if ways.any(|w| Some(w) != first) {
+            return false;
+        }
+    }
+    match (incoming, outgoing) {
+        ([i], [o]) => {
+            let (u, w) = (edges[*i].source, edges[*o].dest);
+            u != w && u != v && w != v
+        }
+        ([i1, i2], [o1, o2]) => {
+            let mut from = [edges[*i1].source, edges[*i2].source];
+            let mut to = [edges[*o1].dest, edges[*o2].dest];
+            from.sort_unstable();
+            to.sort_unstable();
+            from == to && from[0] != from[1] && !from.contains(&v)
+        }
+        _ => false,
+    }
+}
+
+/// Writes the coordinates of the nodes removed from each edge, one line per
+/// edge in the order of the graph file: the number of points followed by
+/// their latitudes and longitudes.
+pub fn write_geometry<W: Write>(mut out: W, edges: &[Edge]) -> Result<()> {
+    writeln!(&mut out, "# Edge geometry, build by: pbfextractor")?;
+    writeln!(&mut out, "{}", edges.len())?;
+    for e in edges {
+        write!(&mut out, "{}", e.geometry.len())?;
+        for (lat, long) in &e.geometry {
+            write!(&mut out, " {} {}", lat, long)?;
+        }
+        writeln!(&mut out)?;
+    }
+    out.flush()
+}
+
+#[test]
+fn contract_two_way_and_one_way_chains() {
+    use super::metrics::Aggregation::*;
+
+    // 0 <-> 1 <-> 2 <-> 3, 3 -> 4 -> 5 -> 3, 3 <-> 6
+    let nodes: Vec<Node> = (0..7)
+        .map(|i| Node::new(i, 48.0, 9.0 + i as f64 * 0.001, 0.0))
+        .collect();
+    let pairs = [
+        (0, 1),
+        (1, 0),
+        (1, 2),
+        (2, 1),
+        (2, 3),
+        (3, 2),
+        (3, 4),
+        (4, 5),
+        (5, 3),
+        (3, 6),
+        (6, 3),
+    ];
+    let edges: Vec<Edge> = pairs
+        .iter()
+        .map(|(s, d)| Edge::with_costs(*s, *d, vec![1.0, *s as f64, 2.0]))
+        .collect();
+
+    let (nodes, edges) = contract_chains(
+        nodes,
+        edges,
+        &[Sum, Max, WeightedMean],
+        &HashSet::new(),
+        false,
+        true,
+    );
+    assert_eq!(
+        vec![0, 3, 6],
+        nodes.iter().map(|n| n.osm_id).collect::<Vec<_>>()
+    );
+    // the 3 -> 4 -> 5 -> 3 loop is dropped
+    let expected = vec![
+        Edge::with_costs(0, 1, vec![3.0, 2.0, 2.0]),
+        Edge::with_costs(1, 0, vec![3.0, 3.0, 2.0]),
+        Edge::with_costs(1, 2, vec![1.0, 3.0, 2.0]),
+        Edge::with_costs(2, 1, vec![1.0, 6.0, 2.0]),
+    ];
+    assert!(edges == expected);
+    assert_eq!(vec![(48.0, 9.001), (48.0, 9.002)], edges[0].geometry);
+}
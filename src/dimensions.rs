@@ -0,0 +1,209 @@
+/*
+Pbfextractor creates graph files for the cycle-routing projects from pbf and srtm data
+Copyright (C) 2018  Florian Barth
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Parsing of the physical restrictions of ways like `maxweight` or
+//! `maxheight` and checking them against the dimensions of a vehicle.
+//!
+//! Lengths default to meters and may be given in `cm`, `ft`/`in` or as
+//! `12'6"`, weights default to tonnes and may be given in `kg`, `lbs` or
+//! short tons (`st`). A decimal comma is accepted.
+
+#[cfg(test)]
+use super::testing::tags;
+use super::units::{Meters, Tonnes};
+
+use osmpbfreader::Tags;
+
+use std::str::FromStr;
+
+const FOOT: f64 = 0.3048;
+const INCH: f64 = 0.0254;
+const SHORT_TON: f64 = 0.907_184_74;
+const POUND: f64 = 0.000_453_592_37;
+
+/// Sums up the quantities of a value like `12 ft 6 in`, converting each
+/// unit with `factor`. Values that are not positive give `None`.
+fn parse_quantity(value: &str, factor: impl Fn(&str) -> Option<f64>) -> Option<f64> {
+    let value = value
+        .trim()
+        .replace(',', ".")
+        .replace('′', "'")
+        .replace('″', "\"");
+    let mut rest = value.as_str();
+    let mut total = 0.0;
+    while !rest.is_empty() {
+        let number_end = rest
+            .find(|c: char| !c.is_ascii_digit() && c != '.')
+            .unwrap_or(rest.len());
+        let number: f64 = rest[..number_end].parse().ok()?;
+        rest = rest[number_end..].trim_start();
+        let unit_end = rest
+            .find(|c: char| c.is_ascii_digit() || c.is_whitespace())
+            .unwrap_or(rest.len());
+        total += number * factor(&rest[..unit_end])?;
+        rest = rest[unit_end..].trim_start();
+    }
+    if total > 0.0 {
+        Some(total)
+    } else {
+        None
+    }
+}
+
+/// Parses a length like `4 m`, `4.5` or `12'6"`. Values without a number
+/// like `none` or `default` give `None`.
+pub fn parse_length(value: &str) -> Option<Meters> {
+    parse_quantity(value, |unit| match unit {
+        "" | "m" => Some(1.0),
+        "cm" => Some(0.01),
+        "mm" => Some(0.001),
+        "ft" | "'" => Some(FOOT),
+        "in" | "\"" => Some(INCH),
+        _ => None,
+    })
+    .map(Meters)
+}
+
+/// Parses a weight like `3.5 t`, `7500 kg` or `10 st`.
+pub fn parse_weight(value: &str) -> Option<Tonnes> {
+    parse_quantity(value, |unit| match unit {
+        "" | "t" => Some(1.0),
+        "kg" => Some(0.001),
+        "st" => Some(SHORT_TON),
+        "lb" | "lbs" => Some(POUND),
+        _ => None,
+    })
+    .map(Tonnes)
+}
+
+impl FromStr for Meters {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_length(s)
+            .ok_or_else(|| format!("expected a length like '4 m' or 12'6\", found '{}'", s))
+    }
+}
+
+impl FromStr for Tonnes {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_weight(s).ok_or_else(|| format!("expected a weight like '7.5 t', found '{}'", s))
+    }
+}
+
+/// The dimensions of a vehicle, `None` for dimensions that are not checked.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct VehicleDimensions {
+    pub weight: Option<Tonnes>,
+    pub axle_load: Option<Tonnes>,
+    pub height: Option<Meters>,
+    pub width: Option<Meters>,
+    pub length: Option<Meters>,
+}
+
+impl VehicleDimensions {
+    /// Whether a restriction of the way is lower than the vehicle. Limits
+    /// that cannot be parsed are ignored.
+    pub fn exceed_limits(&self, tags: &Tags) -> bool {
+        let below = |keys: &[&str], size: Option<f64>, parse: fn(&str) -> Option<f64>| {
+            size.map(|size| {
+                keys.iter()
+                    .filter_map(|k| tags.get(*k))
+                    .filter_map(|v| parse(v))
+                    .any(|limit| limit < size)
+            })
+            .unwrap_or(false)
+        };
+        let length = |v: &str| parse_length(v).map(|m| m.0);
+        let weight = |v: &str| parse_weight(v).map(|t| t.0);
+
+        below(
+            &["maxweight", "maxweight:hgv"],
+            self.weight.map(|t| t.0),
+            weight,
+        ) || below(&["maxaxleload"], self.axle_load.map(|t| t.0), weight)
+            || below(
+                &["maxheight", "maxheight:physical"],
+                self.height.map(|m| m.0),
+                length,
+            )
+            || below(
+                &["maxwidth", "maxwidth:physical"],
+                self.width.map(|m| m.0),
+                length,
+            )
+            || below(
+                &["maxlength", "maxlength:hgv"],
+                self.length.map(|m| m.0),
+                length,
+            )
+    }
+}
+
+#[test]
+fn parse_values() {
+    assert_eq!(Some(Meters(4.0)), parse_length("4 m"));
+    assert_eq!(Some(Meters(4.0)), parse_length("4"));
+    assert_eq!(Some(Meters(3.8)), parse_length("3,8m"));
+    assert_eq!(Some(Meters(2.5)), parse_length("250 cm"));
+    assert_eq!(
+        Some(Meters(12.0 * FOOT + 6.0 * INCH)),
+        parse_length("12'6\"")
+    );
+    assert_eq!(
+        Some(Meters(12.0 * FOOT + 6.0 * INCH)),
+        parse_length("12 ft 6 in")
+    );
+    assert_eq!(Some(Meters(14.0 * FOOT)), parse_length("14'"));
+    assert_eq!(None, parse_length("default"));
+    assert_eq!(None, parse_length("below_default"));
+    assert_eq!(None, parse_length("4 furlongs"));
+    assert_eq!(None, parse_length("0"));
+
+    assert_eq!(Some(Tonnes(3.5)), parse_weight("3.5 t"));
+    assert_eq!(Some(Tonnes(7.5)), parse_weight("7500 kg"));
+    assert_eq!(Some(Tonnes(10.0 * SHORT_TON)), parse_weight("10 st"));
+    assert_eq!(None, parse_weight("none"));
+}
+
+#[test]
+fn vehicle_limits() {
+    let truck = VehicleDimensions {
+        weight: Some(Tonnes(12.0)),
+        axle_load: Some(Tonnes(8.0)),
+        height: Some(Meters(4.0)),
+        width: Some(Meters(2.55)),
+        length: Some(Meters(12.0)),
+    };
+    let exceeds = |pairs: &[(&str, &str)]| truck.exceed_limits(&tags(pairs));
+    assert!(!exceeds(&[("highway", "primary")]));
+    assert!(exceeds(&[("maxweight", "7.5 t")]));
+    assert!(!exceeds(&[("maxweight", "40")]));
+    assert!(exceeds(&[("maxweight:hgv", "3500 kg")]));
+    assert!(exceeds(&[("maxaxleload", "6")]));
+    assert!(exceeds(&[("maxheight", "12'6\"")]));
+    assert!(!exceeds(&[("maxheight", "4.2 m")]));
+    assert!(!exceeds(&[("maxheight", "default")]));
+    assert!(exceeds(&[("maxwidth", "2.3")]));
+    assert!(exceeds(&[("maxlength", "10 m")]));
+
+    // unconfigured dimensions are not checked
+    let car = VehicleDimensions::default();
+    assert!(!car.exceed_limits(&tags(&[("maxweight", "3.5"), ("maxheight", "2")])));
+}
@@ -0,0 +1,693 @@
+/*
+Pbfextractor creates graph files for the cycle-routing projects from pbf and srtm data
+Copyright (C) 2018  Florian Barth
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use super::metrics::{Distance, NodeMetric};
+use super::pbf::{ExtractError, Latitude, Longitude, Node};
+use super::srtm::SrtmCache;
+#[cfg(test)]
+use super::testing::temp_dir;
+use super::units::Meters;
+
+use tiff::decoder::{Decoder, DecodingResult};
+use tiff::tags::Tag;
+use tiff::ColorType;
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+use std::str::FromStr;
+
+/// Provides the height of nodes. Lookups may happen from several threads.
+pub trait ElevationSource: Send + Sync {
+    /// The height in meters at the given coordinates.
+    fn height(&self, lat: Latitude, lng: Longitude) -> Result<f64, ExtractError>;
+}
+
+/// The height used where all samples around a node are voids.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum VoidFallback {
+    /// The mean of the closest valid samples within `VOID_SEARCH_RADIUS`
+    /// samples, 0 if there are none.
+    Neighbours,
+    Height(f64),
+    /// The height is unknown and stored as NaN.
+    Unknown,
+}
+
+/// How many samples away from a void `VoidFallback::Neighbours` looks for
+/// valid samples.
+const VOID_SEARCH_RADIUS: i64 = 10;
+
+impl VoidFallback {
+    /// The height at the fractional `row` and `column` of a grid, see
+    /// `bilinear`, or the fallback if all samples around are voids.
+    pub fn height(self, row: f64, column: f64, sample: impl Fn(u64, u64) -> Option<f64>) -> f64 {
+        if let Some(height) = bilinear(row, column, &sample) {
+            return height;
+        }
+        match self {
+            VoidFallback::Neighbours => nearest_samples(row, column, &sample).unwrap_or(0.0),
+            VoidFallback::Height(height) => height,
+            VoidFallback::Unknown => f64::NAN,
+        }
+    }
+}
+
+/// The mean of the valid samples on the closest square ring around `row`
+/// and `column` that has any.
+fn nearest_samples(row: f64, column: f64, sample: impl Fn(u64, u64) -> Option<f64>) -> Option<f64> {
+    let (row, column) = (row.round() as i64, column.round() as i64);
+    for radius in 1..=VOID_SEARCH_RADIUS {
+        let valid: Vec<f64> = (row - radius..=row + radius)
+            .flat_map(|r| (column - radius..=column + radius).map(move |c| (r, c)))
+            .filter(|(r, c)| (r - row).abs() == radius || (c - column).abs() == radius)
+            .filter(|(r, c)| *r >= 0 && *c >= 0)
+            .filter_map(|(r, c)| sample(r as u64, c as u64))
+            .collect();
+        if !valid.is_empty() {
+            return Some(valid.iter().sum::<f64>() / valid.len() as f64);
+        }
+    }
+    None
+}
+
+impl FromStr for VoidFallback {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "neighbours" => Ok(VoidFallback::Neighbours),
+            "unknown" => Ok(VoidFallback::Unknown),
+            _ => s.parse().map(VoidFallback::Height).map_err(|_| {
+                format!(
+                    "expected a height, 'neighbours' or 'unknown', found '{}'",
+                    s
+                )
+            }),
+        }
+    }
+}
+
+/// What to do with nodes not covered by the elevation data.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MissingTiles {
+    Error,
+    Zero,
+    /// Use the closest sample of the nearest tile.
+    NearestTile,
+}
+
+impl FromStr for MissingTiles {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(MissingTiles::Error),
+            "zero" => Ok(MissingTiles::Zero),
+            "nearest-tile" => Ok(MissingTiles::NearestTile),
+            _ => Err(format!(
+                "expected 'error', 'zero' or 'nearest-tile', found '{}'",
+                s
+            )),
+        }
+    }
+}
+
+/// Interpolates bilinearly between the four samples around the fractional
+/// `row` and `column`. Voids (`None` samples) are left out, if all four are
+/// voids the result is `None`.
+pub fn bilinear(row: f64, column: f64, sample: impl Fn(u64, u64) -> Option<f64>) -> Option<f64> {
+    let row_floor = row.floor() as u64;
+    let row_ceil = row.ceil() as u64;
+    let column_floor = column.floor() as u64;
+    let column_ceil = column.ceil() as u64;
+
+    let samples = [
+        (
+            sample(row_floor, column_floor),
+            (1.0 - row.fract()) * (1.0 - column.fract()),
+        ),
+        (
+            sample(row_ceil, column_floor),
+            row.fract() * (1.0 - column.fract()),
+        ),
+        (
+            sample(row_floor, column_ceil),
+            (1.0 - row.fract()) * column.fract(),
+        ),
+        (sample(row_ceil, column_ceil), row.fract() * column.fract()),
+    ];
+    let valid: Vec<(f64, f64)> = samples
+        .iter()
+        .filter_map(|(h, w)| h.map(|h| (h, *w)))
+        .collect();
+    if valid.is_empty() {
+        return None;
+    }
+    let total: f64 = valid.iter().map(|(_, w)| w).sum();
+    if total > 0.0 {
+        Some(valid.iter().map(|(h, w)| h * w).sum::<f64>() / total)
+    } else {
+        // the node lies exactly on a void
+        Some(valid.iter().map(|(h, _)| h).sum::<f64>() / valid.len() as f64)
+    }
+}
+
+/// A grid of samples in WGS84 coordinates, stored row by row from north to
+/// south.
+pub struct Raster {
+    name: String,
+    rows: usize,
+    columns: usize,
+    /// Coordinates of the center of the north west sample.
+    north: Latitude,
+    west: Longitude,
+    /// Distance between two samples in degrees.
+    cell_lat: f64,
+    cell_lng: f64,
+    values: Vec<f32>,
+    nodata: Option<f32>,
+    void: VoidFallback,
+    missing: MissingTiles,
+}
+
+impl Raster {
+    fn sample(&self, row: u64, column: u64) -> Option<f64> {
+        let row = (row as usize).min(self.rows - 1);
+        let column = (column as usize).min(self.columns - 1);
+        let value = self.values[row * self.columns + column];
+        if value.is_nan() || Some(value) == self.nodata {
+            None
+        } else {
+            Some(f64::from(value))
+        }
+    }
+
+    /// Reads an Esri ASCII grid in WGS84 coordinates.
+    pub fn from_ascii_grid(
+        path: &str,
+        void: VoidFallback,
+        missing: MissingTiles,
+    ) -> Result<Raster, ExtractError> {
+        let content =
+            std::fs::read_to_string(path).map_err(|e| ExtractError::Io(path.to_owned(), e))?;
+        let invalid =
+            |reason: &str| ExtractError::InvalidElevationData(path.to_owned(), reason.to_owned());
+
+        let mut tokens = content.split_whitespace().peekable();
+        let mut header = std::collections::HashMap::new();
+        while let Some(key) = tokens.peek() {
+            if key.parse::<f64>().is_ok() {
+                break;
+            }
+            let key = key.to_ascii_lowercase();
+            tokens.next();
+            let value: f64 = tokens
+                .next()
+                .and_then(|v| v.parse().ok())
+                .ok_or_else(|| invalid(&format!("missing value for {}", key)))?;
+            header.insert(key, value);
+        }
+        let get = |key: &str| {
+            header
+                .get(key)
+                .copied()
+                .ok_or_else(|| invalid(&format!("missing {} in header", key)))
+        };
+        let columns = get("ncols")? as usize;
+        let rows = get("nrows")? as usize;
+        let cell = get("cellsize")?;
+        let (west, south) = match (header.get("xllcenter"), header.get("yllcenter")) {
+            (Some(x), Some(y)) => (*x, *y),
+            _ => (
+                get("xllcorner")? + cell / 2.0,
+                get("yllcorner")? + cell / 2.0,
+            ),
+        };
+        if rows == 0 || columns == 0 || cell <= 0.0 {
+            return Err(invalid("empty grid"));
+        }
+
+        let values: Vec<f32> = tokens
+            .map(str::parse)
+            .collect::<Result<_, _>>()
+            .map_err(|_| invalid("expected numbers after the header"))?;
+        if values.len() != rows * columns {
+            return Err(invalid(&format!(
+                "expected {} samples, found {}",
+                rows * columns,
+                values.len()
+            )));
+        }
+        Ok(Raster {
+            name: path.to_owned(),
+            rows,
+            columns,
+            north: south + (rows - 1) as f64 * cell,
+            west,
+            cell_lat: cell,
+            cell_lng: cell,
+            values,
+            nodata: header.get("nodata_value").map(|v| *v as f32),
+            void,
+            missing,
+        })
+    }
+
+    /// Reads a single band GeoTIFF in geographic WGS84 coordinates.
+    pub fn from_geotiff(
+        path: &str,
+        void: VoidFallback,
+        missing: MissingTiles,
+    ) -> Result<Raster, ExtractError> {
+        let file = File::open(path).map_err(|e| ExtractError::Io(path.to_owned(), e))?;
+        let invalid = |reason: String| ExtractError::InvalidElevationData(path.to_owned(), reason);
+        let tiff_error = |e: tiff::TiffError| invalid(e.to_string());
+
+        let mut decoder = Decoder::new(BufReader::new(file)).map_err(tiff_error)?;
+        let (columns, rows) = decoder.dimensions().map_err(tiff_error)?;
+        match decoder.colortype().map_err(tiff_error)? {
+            ColorType::Gray(_) => {}
+            other => {
+                return Err(invalid(format!(
+                    "expected a single band, found {:?}",
+                    other
+                )))
+            }
+        }
+
+        let keys = decoder
+            .find_tag_unsigned_vec::<u16>(Tag::GeoKeyDirectoryTag)
+            .map_err(tiff_error)?
+            .unwrap_or_default();
+        let geo_key = |id: u16| {
+            keys.get(4..)
+                .unwrap_or(&[])
+                .chunks(4)
+                .find(|k| k.len() == 4 && k[0] == id && k[1] == 0)
+                .map(|k| k[3])
+        };
+        const MODEL_TYPE: u16 = 1024;
+        const RASTER_TYPE: u16 = 1025;
+        const GEOGRAPHIC_TYPE: u16 = 2048;
+        const GEOGRAPHIC: u16 = 2;
+        const PIXEL_IS_POINT: u16 = 2;
+        const WGS84: u16 = 4326;
+        if geo_key(MODEL_TYPE).is_some_and(|t| t != GEOGRAPHIC)
+            || geo_key(GEOGRAPHIC_TYPE).is_some_and(|t| t != WGS84)
+        {
+            return Err(invalid(
+                "only geographic WGS84 coordinates are supported".to_owned(),
+            ));
+        }
+
+        let scale = decoder
+            .get_tag_f64_vec(Tag::ModelPixelScaleTag)
+            .map_err(tiff_error)?;
+        let tie = decoder
+            .get_tag_f64_vec(Tag::ModelTiepointTag)
+            .map_err(tiff_error)?;
+        if scale.len() < 2 || tie.len() < 6 {
+            return Err(invalid("incomplete georeferencing".to_owned()));
+        }
+        let (cell_lng, cell_lat) = (scale[0], scale[1]);
+        let mut west = tie[3] - tie[0] * cell_lng;
+        let mut north = tie[4] + tie[1] * cell_lat;
+        if geo_key(RASTER_TYPE) != Some(PIXEL_IS_POINT) {
+            // the tie point is the corner of the pixel, not its center
+            west += cell_lng / 2.0;
+            north -= cell_lat / 2.0;
+        }
+
+        let nodata = match decoder.find_tag(Tag::GdalNodata).map_err(tiff_error)? {
+            Some(value) => value
+                .into_string()
+                .ok()
+                .and_then(|v| v.trim_matches(char::from(0)).trim().parse().ok()),
+            None => None,
+        };
+        let values: Vec<f32> = match decoder.read_image().map_err(tiff_error)? {
+            DecodingResult::F32(v) => v,
+            DecodingResult::F64(v) => v.into_iter().map(|x| x as f32).collect(),
+            DecodingResult::I16(v) => v.into_iter().map(f32::from).collect(),
+            DecodingResult::U16(v) => v.into_iter().map(f32::from).collect(),
+            DecodingResult::I32(v) => v.into_iter().map(|x| x as f32).collect(),
+            DecodingResult::U32(v) => v.into_iter().map(|x| x as f32).collect(),
+            DecodingResult::I8(v) => v.into_iter().map(f32::from).collect(),
+            DecodingResult::U8(v) => v.into_iter().map(f32::from).collect(),
+            _ => return Err(invalid("unsupported sample format".to_owned())),
+        };
+        Ok(Raster {
+            name: path.to_owned(),
+            rows: rows as usize,
+            columns: columns as usize,
+            north,
+            west,
+            cell_lat,
+            cell_lng,
+            values,
+            nodata,
+            void,
+            missing,
+        })
+    }
+}
+
+impl ElevationSource for Raster {
+    fn height(&self, lat: Latitude, lng: Longitude) -> Result<f64, ExtractError> {
+        let max_row = (self.rows - 1) as f64;
+        let max_column = (self.columns - 1) as f64;
+        let row = (self.north - lat) / self.cell_lat;
+        let column = (lng - self.west) / self.cell_lng;
+        // samples cover half a cell in each direction
+        let inside =
+            (-0.5..=max_row + 0.5).contains(&row) && (-0.5..=max_column + 0.5).contains(&column);
+        if !inside {
+            match self.missing {
+                MissingTiles::Error => {
+                    return Err(ExtractError::OutsideElevationData(
+                        self.name.clone(),
+                        lat,
+                        lng,
+                    ))
+                }
+                MissingTiles::Zero => return Ok(0.0),
+                MissingTiles::NearestTile => {}
+            }
+        }
+        let row = row.max(0.0).min(max_row);
+        let column = column.max(0.0).min(max_column);
+        Ok(self.void.height(row, column, |r, c| self.sample(r, c)))
+    }
+}
+
+/// The elevation profile of the straight line from `source` to `target`
+/// with a sample at least every `spacing` meters. The endpoints keep their
+/// heights, samples with unknown heights are left out.
+pub fn sample_profile(
+    elevation: &dyn ElevationSource,
+    source: &Node,
+    target: &Node,
+    spacing: f64,
+) -> Result<Vec<Node>, ExtractError> {
+    let distance = NodeMetric::<Meters>::calc(&Distance, source, target)
+        .map(|d| d.0)
+        .unwrap_or(0.0);
+    let segments = (distance / spacing).ceil().max(1.0) as usize;
+    let mut profile = Vec::with_capacity(segments + 1);
+    profile.push(source.clone());
+    for i in 1..segments {
+        let t = i as f64 / segments as f64;
+        let lat = source.lat + (target.lat - source.lat) * t;
+        let lng = source.long + (target.long - source.long) * t;
+        // unknown heights would turn both neighbouring segments flat
+        let height = elevation.height(lat, lng)?;
+        if !height.is_nan() {
+            profile.push(Node::new(0, lat, lng, height));
+        }
+    }
+    profile.push(target.clone());
+    Ok(profile)
+}
+
+/// How heights are smoothed along ways to remove noise of the elevation
+/// data.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Smoothing {
+    /// Average of all heights within half this many meters before and after.
+    MovingAverage(f64),
+    /// Heights only change once they differ by more than this many meters.
+    Hysteresis(f64),
+}
+
+impl Smoothing {
+    /// Smoothes the heights of a profile in place. Unknown heights are
+    /// skipped and stay unknown.
+    pub fn apply(self, profile: &mut [Node]) {
+        match self {
+            Smoothing::MovingAverage(window) => {
+                let mut position = vec![0.0; profile.len()];
+                for i in 1..profile.len() {
+                    let step = NodeMetric::<Meters>::calc(&Distance, &profile[i - 1], &profile[i])
+                        .map(|d| d.0)
+                        .unwrap_or(0.0);
+                    position[i] = position[i - 1] + step;
+                }
+                let heights: Vec<f64> = profile.iter().map(|n| n.height).collect();
+                let (mut first, mut last) = (0, 0);
+                for (i, node) in profile.iter_mut().enumerate() {
+                    if node.height.is_nan() {
+                        continue;
+                    }
+                    while position[i] - position[first] > window / 2.0 {
+                        first += 1;
+                    }
+                    while last + 1 < heights.len()
+                        && position[last + 1] - position[i] <= window / 2.0
+                    {
+                        last += 1;
+                    }
+                    let known: Vec<f64> = heights[first..=last]
+                        .iter()
+                        .copied()
+                        .filter(|h| !h.is_nan())
+                        .collect();
+                    node.height = known.iter().sum::<f64>() / known.len() as f64;
+                }
+            }
+            Smoothing::Hysteresis(threshold) => {
+                let mut reference = None;
+                for node in profile.iter_mut().filter(|n| !n.height.is_nan()) {
+                    let current = reference.get_or_insert(node.height);
+                    if node.height > *current + threshold {
+                        *current = node.height - threshold;
+                    } else if node.height < *current - threshold {
+                        *current = node.height + threshold;
+                    }
+                    node.height = *current;
+                }
+            }
+        }
+    }
+}
+
+impl FromStr for Smoothing {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let malformed = || {
+            format!(
+                "expected 'moving-average:<meters>' or 'hysteresis:<meters>', found '{}'",
+                s
+            )
+        };
+        let (kind, value) = s.split_once(':').ok_or_else(malformed)?;
+        let value: f64 = value.trim().parse().map_err(|_| malformed())?;
+        if value.is_nan() || value < 0.0 {
+            return Err(malformed());
+        }
+        match kind.trim() {
+            "moving-average" => Ok(Smoothing::MovingAverage(value)),
+            "hysteresis" => Ok(Smoothing::Hysteresis(value)),
+            _ => Err(malformed()),
+        }
+    }
+}
+
+/// Opens the elevation data given as `srtm:<directory>`, `geotiff:<file>` or
+/// `ascii:<file>`. Without a prefix, files are recognized by their extension
+/// (`.tif`, `.tiff` or `.asc`), anything else is a directory of SRTM tiles.
+pub fn open_elevation_source(
+    spec: &str,
+    srtm_samples: Option<u64>,
+    void: VoidFallback,
+    missing: MissingTiles,
+) -> Result<Box<dyn ElevationSource>, ExtractError> {
+    let (kind, path) = match spec.split_once(':') {
+        Some((kind @ ("srtm" | "geotiff" | "ascii"), path)) => (kind, path),
+        _ => {
+            let path = Path::new(spec);
+            let extension = path
+                .extension()
+                .and_then(|e| e.to_str())
+                .map(str::to_ascii_lowercase);
+            let kind = match extension.as_deref() {
+                _ if path.is_dir() => "srtm",
+                Some("tif") | Some("tiff") => "geotiff",
+                Some("asc") => "ascii",
+                _ => "srtm",
+            };
+            (kind, spec)
+        }
+    };
+    Ok(match kind {
+        "srtm" => Box::new(SrtmCache::new(path, srtm_samples, void, missing)),
+        "geotiff" => Box::new(Raster::from_geotiff(path, void, missing)?),
+        _ => Box::new(Raster::from_ascii_grid(path, void, missing)?),
+    })
+}
+
+#[test]
+fn ascii_grid() {
+    let dir = temp_dir("pbfextractor-ascii-grid");
+    let path = dir.join("dem.asc");
+    std::fs::write(
+        &path,
+        "ncols 3\nnrows 2\nxllcorner 9.0\nyllcorner 48.0\ncellsize 0.5\nNODATA_value -9999\n\
+         10 20 -9999\n30 40 50\n",
+    )
+    .unwrap();
+    let path = path.to_str().unwrap();
+    let raster =
+        Raster::from_ascii_grid(path, VoidFallback::Height(-1.0), MissingTiles::Error).unwrap();
+
+    // sample centers are at latitudes 48.75 and 48.25 and longitudes 9.25,
+    // 9.75 and 10.25
+    assert_eq!(10.0, raster.height(48.75, 9.25).unwrap());
+    assert_eq!(40.0, raster.height(48.25, 9.75).unwrap());
+    assert_eq!(25.0, raster.height(48.5, 9.5).unwrap());
+    assert_eq!(-1.0, raster.height(48.75, 10.25).unwrap());
+    // the void is left out of the interpolation
+    assert_eq!(50.0, raster.height(48.5, 10.25).unwrap());
+    assert_eq!(10.0, raster.height(48.99, 9.01).unwrap());
+    assert!(raster.height(49.1, 9.5).is_err());
+
+    let source =
+        open_elevation_source(path, None, VoidFallback::Unknown, MissingTiles::Zero).unwrap();
+    assert_eq!(0.0, source.height(49.1, 9.5).unwrap());
+}
+
+#[test]
+fn geotiff() {
+    use tiff::encoder::{colortype, TiffEncoder};
+
+    let dir = temp_dir("pbfextractor-geotiff");
+    let path = dir.join("dem.tif");
+    {
+        let file = File::create(&path).unwrap();
+        let mut encoder = TiffEncoder::new(file).unwrap();
+        let mut image = encoder.new_image::<colortype::Gray32Float>(2, 2).unwrap();
+        image
+            .encoder()
+            .write_tag(Tag::ModelPixelScaleTag, &[0.5, 0.5, 0.0][..])
+            .unwrap();
+        image
+            .encoder()
+            .write_tag(
+                Tag::ModelTiepointTag,
+                &[0.0, 0.0, 0.0, -71.0, -33.0, 0.0][..],
+            )
+            .unwrap();
+        // geographic model, pixel is area, WGS84
+        let keys: &[u16] = &[1, 1, 0, 3, 1024, 0, 1, 2, 1025, 0, 1, 1, 2048, 0, 1, 4326];
+        image
+            .encoder()
+            .write_tag(Tag::GeoKeyDirectoryTag, keys)
+            .unwrap();
+        image.encoder().write_tag(Tag::GdalNodata, "-9999").unwrap();
+        image.write_data(&[100.0, 200.0, 300.0, -9999.0]).unwrap();
+    }
+
+    let source = open_elevation_source(
+        path.to_str().unwrap(),
+        None,
+        VoidFallback::Unknown,
+        MissingTiles::NearestTile,
+    )
+    .unwrap();
+    assert_eq!(100.0, source.height(-33.25, -70.75).unwrap());
+    assert_eq!(300.0, source.height(-33.75, -70.75).unwrap());
+    assert_eq!(200.0, source.height(-33.5, -70.25).unwrap());
+    // nearest sample for coordinates outside of the raster
+    assert_eq!(200.0, source.height(-30.0, -60.0).unwrap());
+}
+
+#[test]
+fn profile_samples() {
+    struct Slope;
+    impl ElevationSource for Slope {
+        fn height(&self, lat: Latitude, _: Longitude) -> Result<f64, ExtractError> {
+            Ok((lat - 48.0) * 10_000.0)
+        }
+    }
+
+    // about 111 m
+    let source = Node::new(1, 48.0, 9.0, 1.0);
+    let target = Node::new(2, 48.001, 9.0, 2.0);
+    let profile = sample_profile(&Slope, &source, &target, 30.0).unwrap();
+    assert_eq!(5, profile.len());
+    assert_eq!(1.0, profile[0].height);
+    assert!((profile[2].height - 5.0).abs() < 1e-6);
+    assert_eq!(2.0, profile[4].height);
+
+    let profile = sample_profile(&Slope, &source, &source, 30.0).unwrap();
+    assert_eq!(2, profile.len());
+
+    // voids in between are skipped instead of flattening the climb
+    struct Void;
+    impl ElevationSource for Void {
+        fn height(&self, lat: Latitude, _: Longitude) -> Result<f64, ExtractError> {
+            Ok(if lat < 48.0005 { f64::NAN } else { 200.0 })
+        }
+    }
+    let target = Node::new(2, 48.001, 9.0, 201.0);
+    let profile = sample_profile(&Void, &source, &target, 30.0).unwrap();
+    assert_eq!(4, profile.len());
+    assert!(profile.iter().all(|n| !n.height.is_nan()));
+    let ascent =
+        NodeMetric::<Meters>::calc_profile(&super::metrics::HeightAscent, &profile).unwrap();
+    assert_eq!(200.0, ascent.0);
+}
+
+#[test]
+fn smoothing() {
+    // nodes about 11 m apart
+    let profile = |heights: &[f64]| -> Vec<Node> {
+        heights
+            .iter()
+            .enumerate()
+            .map(|(i, h)| Node::new(i, 48.0 + i as f64 * 0.0001, 9.0, *h))
+            .collect()
+    };
+    let heights = |profile: &[Node]| -> Vec<f64> { profile.iter().map(|n| n.height).collect() };
+
+    let mut jitter = profile(&[100.0, 103.0, 100.0, 103.0, 100.0, 120.0]);
+    Smoothing::Hysteresis(5.0).apply(&mut jitter);
+    assert_eq!(
+        vec![100.0, 100.0, 100.0, 100.0, 100.0, 115.0],
+        heights(&jitter)
+    );
+
+    // a window of 25 m covers the neighbours on both sides
+    let mut jitter = profile(&[100.0, 103.0, f64::NAN, 103.0, 100.0, 103.0]);
+    Smoothing::MovingAverage(25.0).apply(&mut jitter);
+    let smoothed = heights(&jitter);
+    assert_eq!(101.5, smoothed[0]);
+    assert_eq!(101.5, smoothed[1]);
+    assert!(smoothed[2].is_nan());
+    assert_eq!(101.5, smoothed[3]);
+    assert_eq!(102.0, smoothed[4]);
+    assert_eq!(101.5, smoothed[5]);
+
+    assert_eq!(Ok(Smoothing::Hysteresis(5.0)), "hysteresis:5".parse());
+    assert_eq!(
+        Ok(Smoothing::MovingAverage(100.0)),
+        "moving-average:100".parse()
+    );
+    assert!("average:100".parse::<Smoothing>().is_err());
+    assert!("hysteresis:-1".parse::<Smoothing>().is_err());
+}
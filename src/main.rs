@@ -19,32 +19,165 @@
 extern crate byteorder;
 extern crate osmpbfreader;
 
+mod access;
+mod area;
+mod binary;
+mod components;
+mod config;
+mod contraction;
+mod dimensions;
+mod elevation;
+mod maxspeed;
 mod metrics;
 mod pbf;
+mod profile;
+mod restrictions;
+mod script;
+mod srtm;
+#[cfg(test)]
+mod testing;
 mod units;
 
+use self::area::Area;
+use self::binary::write_binary_graph;
+use self::components::ComponentFilter;
+use self::config::*;
+use self::contraction::write_geometry;
+use self::dimensions::VehicleDimensions;
+use self::elevation::{open_elevation_source, MissingTiles, Smoothing, VoidFallback};
 use self::metrics::*;
 use self::pbf::*;
+use self::profile::Profile;
+use self::restrictions::write_restrictions;
+use self::script::ScriptedMetric;
+use self::srtm::samples_for_resolution;
+use self::units::{Meters, Tonnes};
 
-use clap::Arg;
-use clap::{arg, Command};
+use clap::{arg, Arg, ArgAction, Command};
 use std::fs::File;
 use std::io::{BufWriter, Write};
-use std::rc::Rc;
 use std::time::SystemTime;
 
 fn main() {
     let app = Command::new("PBF Extractor")
         .author("Florian Barth")
         .about("Extracts Graphs with multidimensional costs from PBF files")
+        .allow_missing_positional(true)
         .args(&[
-            arg!(zipped: -z ... "saves graph gzipped"),
+            arg!(zipped: -z "saves graph gzipped"),
+            Arg::new("format")
+                .long("format")
+                .short('f')
+                .help("Format of the graph file")
+                .value_parser(["text", "binary"])
+                .default_value("text"),
+            Arg::new("profile")
+                .long("profile")
+                .short('p')
+                .help("Vehicle profile deciding which ways are part of the graph (car, bicycle, truck, foot)")
+                .value_parser(clap::value_parser!(VehicleProfile))
+                .default_value("car"),
+            Arg::new("truck-weight")
+                .long("truck-weight")
+                .help("Weight of the truck, e.g. '7.5 t', excludes ways with a lower maxweight")
+                .value_parser(clap::value_parser!(Tonnes)),
+            Arg::new("truck-axle-load")
+                .long("truck-axle-load")
+                .help("Axle load of the truck, e.g. '8 t', excludes ways with a lower maxaxleload")
+                .value_parser(clap::value_parser!(Tonnes)),
+            Arg::new("truck-height")
+                .long("truck-height")
+                .help("Height of the truck, e.g. '4 m' or 13'1\", excludes ways with a lower maxheight")
+                .value_parser(clap::value_parser!(Meters)),
+            Arg::new("truck-width")
+                .long("truck-width")
+                .help("Width of the truck, excludes ways with a lower maxwidth")
+                .value_parser(clap::value_parser!(Meters)),
+            Arg::new("truck-length")
+                .long("truck-length")
+                .help("Length of the truck, excludes ways with a lower maxlength")
+                .value_parser(clap::value_parser!(Meters)),
+            Arg::new("profile-file")
+                .long("profile-file")
+                .help("TOML or JSON file describing edge filter, metric tables and metrics")
+                .conflicts_with("profile"),
+            Arg::new("metric")
+                .long("metric")
+                .short('m')
+                .help("Metric written to the graph, e.g. 'Distance' or 'TravelTime:Distance/CarSpeed'")
+                .action(ArgAction::Append),
+            Arg::new("internal-metric")
+                .long("internal-metric")
+                .help("Metric only calculated as a dependency of other metrics")
+                .action(ArgAction::Append),
+            Arg::new("script")
+                .long("script")
+                .help("Scripted metric given as 'name:kind:expression', kind is tag, node or cost")
+                .value_parser(clap::value_parser!(ScriptedMetric))
+                .action(ArgAction::Append),
+            Arg::new("restrictions")
+                .long("restrictions")
+                .help("File to write the turn restrictions of the graph to"),
+            Arg::new("bbox")
+                .long("bbox")
+                .help("Only extract the area minlon,minlat,maxlon,maxlat")
+                .value_parser(clap::value_parser!(Area))
+                .allow_hyphen_values(true),
+            Arg::new("poly")
+                .long("poly")
+                .help("Only extract the area of an Osmosis polygon file")
+                .conflicts_with("bbox"),
+            Arg::new("largest-component")
+                .long("largest-component")
+                .help("Only keep the largest strongly connected component")
+                .action(ArgAction::SetTrue),
+            Arg::new("min-component-size")
+                .long("min-component-size")
+                .help("Drop strongly connected components with fewer nodes")
+                .value_parser(clap::value_parser!(usize))
+                .conflicts_with("largest-component"),
+            Arg::new("simplify")
+                .long("simplify")
+                .help("Merge chains of degree-2 nodes into single edges")
+                .action(ArgAction::SetTrue),
+            Arg::new("geometry")
+                .long("geometry")
+                .help("File to write the coordinates of nodes removed by --simplify to")
+                .requires("simplify"),
+            Arg::new("missing-nodes")
+                .long("missing-nodes")
+                .help("What to do with ways referencing nodes missing from the pbf file: stop, drop the whole way or split it at the missing node")
+                .value_parser(["abort", "drop", "split"])
+                .default_value("abort"),
+            Arg::new("srtm-resolution")
+                .long("srtm-resolution")
+                .help("Resolution of the srtm files in arc seconds, detected from the file size by default")
+                .value_parser(clap::value_parser!(f64)),
+            Arg::new("elevation-void")
+                .long("elevation-void")
+                .alias("srtm-void")
+                .help("Height of nodes surrounded by voids in the elevation data: 'neighbours' (mean of the closest valid samples, else 0), a number of meters or 'unknown' (NaN)")
+                .value_parser(clap::value_parser!(VoidFallback))
+                .default_value("neighbours"),
+            Arg::new("missing-elevation")
+                .long("missing-elevation")
+                .alias("missing-srtm")
+                .help("What to do with nodes not covered by the elevation data: error, zero or nearest-tile")
+                .value_parser(clap::value_parser!(MissingTiles))
+                .default_value("error"),
+            Arg::new("elevation-spacing")
+                .long("elevation-spacing")
+                .help("Sample the elevation data every this many meters along edges, e.g. 30, instead of only at their nodes")
+                .value_parser(clap::value_parser!(f64)),
+            Arg::new("elevation-smoothing")
+                .long("elevation-smoothing")
+                .help("Smooth heights along ways: 'moving-average:<window in meters>' or 'hysteresis:<threshold in meters>'")
+                .value_parser(clap::value_parser!(Smoothing)),
             Arg::new("PBF-FILE")
                 .help("PBF File to extract from")
                 .required(true),
-            Arg::new("SRTM")
-                .help("Directory with srtm files")
-                .required(true),
+            Arg::new("ELEVATION")
+                .help("Elevation data, only needed by metrics using heights: a directory with srtm files, a GeoTIFF (.tif) or an Esri ASCII grid (.asc), optionally prefixed with srtm:, geotiff: or ascii:"),
             Arg::new("GRAPH")
                 .help("File to write graph to")
                 .required(true),
@@ -53,90 +186,313 @@ fn main() {
     let matches = app.get_matches();
 
     let zip = matches.get_flag("zipped");
+    let binary = matches.get_one::<String>("format").map(String::as_str) == Some("binary");
+    let restrictions_output = matches.get_one::<String>("restrictions");
+    let geometry_output = matches.get_one::<String>("geometry");
+    let mut area = matches.get_one::<Area>("bbox").cloned();
+    if let Some(path) = matches.get_one::<String>("poly") {
+        match Area::from_poly_file(path) {
+            Ok(poly) => area = Some(poly),
+            Err(e) => {
+                eprintln!("error: {}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+    }
+
+    let components = if matches.get_flag("largest-component") {
+        ComponentFilter::Largest
+    } else if let Some(size) = matches.get_one::<usize>("min-component-size") {
+        ComponentFilter::MinSize(*size)
+    } else {
+        ComponentFilter::All
+    };
+
+    let missing_nodes = match matches
+        .get_one::<String>("missing-nodes")
+        .map(String::as_str)
+    {
+        Some("drop") => MissingNodes::DropWay,
+        Some("split") => MissingNodes::Split,
+        _ => MissingNodes::Abort,
+    };
+
+    let srtm_samples =
+        matches
+            .get_one::<f64>("srtm-resolution")
+            .map(|r| match samples_for_resolution(*r) {
+                Some(samples) => samples,
+                None => {
+                    eprintln!(
+                        "error: a degree cannot be divided into samples of {} arc seconds",
+                        r
+                    );
+                    std::process::exit(1);
+                }
+            });
+
+    let elevation_spacing = matches.get_one::<f64>("elevation-spacing").copied();
+    if elevation_spacing.is_some_and(|s| s.is_nan() || s <= 0.0) {
+        eprintln!("error: the elevation spacing has to be a positive number of meters");
+        std::process::exit(1);
+    }
 
     let pbf_input = matches
         .get_one::<String>("PBF-FILE")
         .expect("No PBF File to extract from");
-    let srtm_input = matches.get_one::<String>("SRTM").expect("No srtm input file given");
-    let output = matches.get_one::<String>("GRAPH").expect("No output file given");
+    let elevation_input = matches.get_one::<String>("ELEVATION");
+    let output = matches
+        .get_one::<String>("GRAPH")
+        .expect("No output file given");
+    let profile = *matches
+        .get_one::<VehicleProfile>("profile")
+        .expect("No profile given");
+    let mut metrics: Vec<String> = matches
+        .get_many::<String>("metric")
+        .map(|m| m.cloned().collect())
+        .unwrap_or_default();
+    let mut internal: Vec<String> = matches
+        .get_many::<String>("internal-metric")
+        .map(|m| m.cloned().collect())
+        .unwrap_or_default();
     let grid = Grid::new_ptr();
 
-    let dist = Rc::new(Distance);
-    let car = Rc::new(CarSpeed);
-    let fast_car = Rc::new(FastCarSpeed);
-    let truck = Rc::new(TruckSpeed);
+    let dimensions = VehicleDimensions {
+        weight: matches.get_one::<Tonnes>("truck-weight").copied(),
+        axle_load: matches.get_one::<Tonnes>("truck-axle-load").copied(),
+        height: matches.get_one::<Meters>("truck-height").copied(),
+        width: matches.get_one::<Meters>("truck-width").copied(),
+        length: matches.get_one::<Meters>("truck-length").copied(),
+    };
+    let profile_file = matches.get_one::<String>("profile-file");
+    if dimensions != VehicleDimensions::default()
+        && profile != VehicleProfile::Truck
+        && profile_file.is_none()
+    {
+        eprintln!("error: vehicle dimensions are only used by the truck profile or a profile file");
+        std::process::exit(1);
+    }
 
-    let _grid_x = Rc::new(GridX(grid.clone()));
-    let _grid_y = Rc::new(GridY(grid.clone()));
-    let _chess = Rc::new(ChessBoard(grid.clone()));
+    let mut edge_filter = profile.edge_filter(dimensions);
+    let mut restriction_modes = Some(profile.modes().iter().map(|m| m.to_string()).collect());
+    let mut custom_metrics = Vec::new();
+    if let Some(path) = profile_file {
+        let profile = match Profile::from_file(path) {
+            Ok(profile) => profile,
+            Err(e) => {
+                eprintln!("error: {}: {}", path, e);
+                std::process::exit(1);
+            }
+        };
+        custom_metrics = profile
+            .tag_metrics()
+            .into_iter()
+            .map(AnyMetric::Tag)
+            .collect();
+        custom_metrics.extend(profile.node_metrics().into_iter().map(AnyMetric::Node));
+        match profile.scripts() {
+            Ok(scripts) => custom_metrics.extend(scripts.into_iter().map(AnyMetric::from)),
+            Err(e) => {
+                eprintln!("error: {}: {}", path, e);
+                std::process::exit(1);
+            }
+        }
+        if metrics.is_empty() {
+            metrics = profile.metrics.export;
+            internal.extend(profile.metrics.internal);
+        }
+        restriction_modes = Some(profile.filter.access_keys.clone()).filter(|k| !k.is_empty());
+        edge_filter = Box::new(DimensionFilter {
+            filter: profile.filter,
+            dimensions,
+        });
+    }
 
-    let _car_time = Rc::new(TravelTime::new(dist.clone(), car));
-    let _fast_car_time = Rc::new(TravelTime::new(dist.clone(), fast_car));
-    let _truck_time = Rc::new(TravelTime::new(dist.clone(), truck));
+    if let Some(scripts) = matches.get_many::<ScriptedMetric>("script") {
+        custom_metrics.extend(scripts.cloned().map(AnyMetric::from));
+    }
 
-    let _random = Rc::new(RandomWeights);
+    if metrics.is_empty() {
+        metrics.push("Distance".to_owned());
+    }
+    let selection = match MetricSelection::from_specs(&metrics, &internal, &custom_metrics, &grid) {
+        Ok(selection) => selection,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
+        }
+    };
 
-    let internal_only_metrics: InternalMetrics = vec![].into_iter().collect();
+    let smoothing = matches.get_one::<Smoothing>("elevation-smoothing").copied();
+    if selection.height_metric().is_none() {
+        let unused = if elevation_spacing.is_some() {
+            Some("--elevation-spacing")
+        } else if smoothing.is_some() {
+            Some("--elevation-smoothing")
+        } else {
+            None
+        };
+        if let Some(option) = unused {
+            eprintln!("error: {} given, but no metric uses heights", option);
+            std::process::exit(1);
+        }
+    }
 
-    let tag_metrics: TagMetrics = vec![];
-    let node_metrics: NodeMetrics = vec![dist];
-    let cost_metrics: CostMetrics = vec![];
+    let elevation = match (selection.height_metric(), elevation_input) {
+        (Some(_), Some(input)) => match open_elevation_source(
+            input,
+            srtm_samples,
+            *matches
+                .get_one::<VoidFallback>("elevation-void")
+                .expect("has a default"),
+            *matches
+                .get_one::<MissingTiles>("missing-elevation")
+                .expect("has a default"),
+        ) {
+            Ok(elevation) => Some(elevation),
+            Err(e) => {
+                eprintln!("error: {}", e);
+                std::process::exit(1);
+            }
+        },
+        (None, None) => None,
+        (Some(metric), None) => {
+            eprintln!("error: metric {} needs elevation data", metric);
+            std::process::exit(1);
+        }
+        (None, Some(input)) => {
+            eprintln!(
+                "error: elevation data {} given, but no metric uses heights",
+                input
+            );
+            std::process::exit(1);
+        }
+    };
 
     let l = pbf::Loader::new(
         pbf_input,
-        srtm_input,
-        CarEdgeFilter,
-        tag_metrics,
-        node_metrics,
-        cost_metrics,
-        internal_only_metrics,
+        elevation,
+        edge_filter,
+        selection.tag_metrics,
+        selection.node_metrics,
+        selection.cost_metrics,
+        selection.internal_metrics,
         grid,
+        LoaderOptions {
+            turn_restrictions: restrictions_output.is_some(),
+            restriction_modes,
+            area,
+            components,
+            simplify: matches.get_flag("simplify"),
+            keep_geometry: geometry_output.is_some(),
+            missing_nodes,
+            elevation_spacing,
+            smoothing,
+        },
     );
 
-    let output_file = File::create(output).unwrap();
-    let graph = BufWriter::new(output_file);
-    if zip {
-        let graph = flate2::write::GzEncoder::new(graph, flate2::Compression::best());
-        write_graph(&l, graph);
-    } else {
-        write_graph(&l, graph);
+    let result = File::create(output)
+        .map_err(|e| ExtractError::Io(output.clone(), e))
+        .and_then(|output_file| {
+            let graph = BufWriter::new(output_file);
+            if zip {
+                let graph = flate2::write::GzEncoder::new(graph, flate2::Compression::best());
+                write_graph(
+                    &l,
+                    graph,
+                    output,
+                    binary,
+                    restrictions_output,
+                    geometry_output,
+                )
+            } else {
+                write_graph(
+                    &l,
+                    graph,
+                    output,
+                    binary,
+                    restrictions_output,
+                    geometry_output,
+                )
+            }
+        });
+    if let Err(e) = result {
+        eprintln!("error: {}", e);
+        std::process::exit(1);
     }
 }
 
-fn write_graph<T: EdgeFilter, W: Write>(l: &Loader<T>, mut graph: W) {
-    let (nodes, edges) = l.load_graph();
+fn write_graph<T: EdgeFilter, W: Write>(
+    l: &Loader<T>,
+    mut graph: W,
+    output: &str,
+    binary: bool,
+    restrictions_output: Option<&String>,
+    geometry_output: Option<&String>,
+) -> Result<(), ExtractError> {
+    let (nodes, edges, restrictions) = l.load_graph()?;
 
-    writeln!(&mut graph, "# Build by: pbfextractor").unwrap();
-    writeln!(&mut graph, "# Build on: {:?}", SystemTime::now()).unwrap();
-    write!(&mut graph, "# metrics: ").unwrap();
+    if let Some(path) = restrictions_output {
+        File::create(path)
+            .and_then(|file| write_restrictions(BufWriter::new(file), &restrictions))
+            .map_err(|e| ExtractError::Io(path.clone(), e))?;
+    }
+    if let Some(path) = geometry_output {
+        File::create(path)
+            .and_then(|file| write_geometry(BufWriter::new(file), &edges))
+            .map_err(|e| ExtractError::Io(path.clone(), e))?;
+    }
+
+    if binary {
+        write_binary_graph(
+            &mut graph,
+            &nodes,
+            &edges,
+            &l.metrics_indices,
+            &l.internal_metrics,
+        )
+    } else {
+        write_text_graph(l, &nodes, &edges, graph)
+    }
+    .map_err(|e| ExtractError::Io(output.to_owned(), e))
+}
+
+fn write_text_graph<T: EdgeFilter, W: Write>(
+    l: &Loader<T>,
+    nodes: &[Node],
+    edges: &[Edge],
+    mut graph: W,
+) -> std::io::Result<()> {
+    writeln!(&mut graph, "# Build by: pbfextractor")?;
+    writeln!(&mut graph, "# Build on: {:?}", SystemTime::now())?;
+    write!(&mut graph, "# metrics: ")?;
 
     for metric in l.metrics_indices.keys() {
         if l.internal_metrics.contains(metric) {
             continue;
         }
-        write!(&mut graph, "{}, ", metric).unwrap();
+        write!(&mut graph, "{}, ", metric)?;
     }
 
-    write!(&mut graph, "\n\n").unwrap();
+    write!(&mut graph, "\n\n")?;
 
-    writeln!(&mut graph, "{}", l.metric_count()).unwrap();
-    writeln!(&mut graph, "{}", nodes.len()).unwrap();
-    writeln!(&mut graph, "{}", edges.len()).unwrap();
+    writeln!(&mut graph, "{}", l.metric_count())?;
+    writeln!(&mut graph, "{}", nodes.len())?;
+    writeln!(&mut graph, "{}", edges.len())?;
 
     for (i, node) in nodes.iter().enumerate() {
         writeln!(
             &mut graph,
             "{} {} {} {} {} 0",
             i, node.osm_id, node.lat, node.long, node.height,
-        )
-        .unwrap();
+        )?;
     }
-    for edge in &edges {
-        write!(&mut graph, "{} {} ", edge.source, edge.dest).unwrap();
+    for edge in edges {
+        write!(&mut graph, "{} {} ", edge.source, edge.dest)?;
         for cost in &edge.costs(&l.metrics_indices, &l.internal_metrics) {
-            write!(&mut graph, "{} ", cost.round()).unwrap();
+            write!(&mut graph, "{} ", cost.round())?;
         }
-        writeln!(&mut graph, "-1 -1").unwrap();
+        writeln!(&mut graph, "-1 -1")?;
     }
-    graph.flush().unwrap();
+    graph.flush()
 }
@@ -0,0 +1,280 @@
+/*
+Pbfextractor creates graph files for the cycle-routing projects from pbf and srtm data
+Copyright (C) 2018  Florian Barth
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Parsing of the `maxspeed` tags of ways.
+//!
+//! Values are numbers in km/h, optionally followed by a unit (`mph`,
+//! `knots`), `none`, `walk` or an implicit zone like `FR:urban`. Several
+//! values separated by `;` give the lowest of them.
+
+use super::access::Direction;
+#[cfg(test)]
+use super::testing::tags;
+
+use osmpbfreader::Tags;
+
+/// A speed limit in km/h, `Unlimited` for `maxspeed=none`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MaxSpeed {
+    Limit(f64),
+    Unlimited,
+}
+
+impl MaxSpeed {
+    fn min(self, other: MaxSpeed) -> MaxSpeed {
+        match (self, other) {
+            (MaxSpeed::Limit(a), MaxSpeed::Limit(b)) => MaxSpeed::Limit(a.min(b)),
+            (MaxSpeed::Limit(a), MaxSpeed::Unlimited)
+            | (MaxSpeed::Unlimited, MaxSpeed::Limit(a)) => MaxSpeed::Limit(a),
+            (MaxSpeed::Unlimited, MaxSpeed::Unlimited) => MaxSpeed::Unlimited,
+        }
+    }
+}
+
+/// The vehicle a speed limit is looked up for.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Vehicle {
+    Car,
+    /// Uses `maxspeed:hgv` and conditional limits for heavy vehicles.
+    Hgv,
+}
+
+const MPH: f64 = 1.609_344;
+const KNOTS: f64 = 1.852;
+
+/// Parses a `maxspeed` value. Unknown values like `signals` give `None`.
+pub fn parse_maxspeed(value: &str) -> Option<MaxSpeed> {
+    value
+        .split(';')
+        .map(parse_single)
+        .collect::<Option<Vec<_>>>()?
+        .into_iter()
+        .reduce(MaxSpeed::min)
+}
+
+fn parse_single(value: &str) -> Option<MaxSpeed> {
+    let value = value.trim();
+    match value {
+        "none" => return Some(MaxSpeed::Unlimited),
+        "walk" | "living_street" => return Some(MaxSpeed::Limit(10.0)),
+        _ => {}
+    }
+    if value.contains(':') {
+        return zone_speed(value);
+    }
+
+    let number_end = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let number: f64 = value[..number_end].parse().ok()?;
+    let factor = match value[number_end..].trim() {
+        "" | "km/h" | "kmh" | "kph" => 1.0,
+        "mph" => MPH,
+        "knots" => KNOTS,
+        _ => return None,
+    };
+    if number > 0.0 {
+        Some(MaxSpeed::Limit(number * factor))
+    } else {
+        None
+    }
+}
+
+/// The speed limits implied by zones like `DE:rural`. Zones of the form
+/// `XX:zone30` or `XX:zone:30` give the number.
+fn zone_speed(zone: &str) -> Option<MaxSpeed> {
+    let (_, kind) = zone.split_once(':')?;
+    if let Some(speed) = kind
+        .strip_prefix("zone")
+        .map(|s| s.trim_start_matches(':'))
+        .and_then(|s| s.parse::<f64>().ok())
+    {
+        return Some(MaxSpeed::Limit(speed));
+    }
+    let speed = match zone {
+        "AT:urban" | "BE:urban" | "CH:urban" | "CZ:urban" | "DE:urban" | "DK:urban"
+        | "ES:urban" | "FI:urban" | "FR:urban" | "IT:urban" | "NL:urban" | "NO:urban"
+        | "PL:urban" | "PT:urban" | "RO:urban" | "SE:urban" => 50.0,
+        "RU:urban" => 60.0,
+        "SE:rural" | "BE-VLG:rural" => 70.0,
+        "CH:rural" | "DK:rural" | "FI:rural" | "FR:rural" | "NL:rural" | "NO:rural" => 80.0,
+        "BE:rural" | "CZ:rural" | "ES:rural" | "IT:rural" | "PL:rural" | "PT:rural"
+        | "RO:rural" | "RU:rural" | "NO:motorway" => 90.0,
+        "AT:rural" | "AT:trunk" | "CH:trunk" | "DE:rural" | "ES:trunk" | "NL:trunk"
+        | "NL:motorway" | "PL:trunk" | "PT:trunk" | "RO:trunk" => 100.0,
+        "CZ:trunk" | "FR:trunk" | "IT:trunk" | "RU:motorway" | "SE:motorway" => 110.0,
+        "BE:trunk" | "BE:motorway" | "CH:motorway" | "ES:motorway" | "FI:motorway"
+        | "PL:expressway" | "PT:motorway" => 120.0,
+        "AT:motorway" | "CZ:motorway" | "DK:motorway" | "FR:motorway" | "IT:motorway"
+        | "RO:motorway" => 130.0,
+        "PL:motorway" => 140.0,
+        "DE:motorway" => return Some(MaxSpeed::Unlimited),
+        "DE:living_street" | "DE:walk" => 10.0,
+        "RU:living_street" => 20.0,
+        "DE:bicycle_road" => 30.0,
+        "GB:nsl_restricted" => 30.0 * MPH,
+        "GB:nsl_single" => 60.0 * MPH,
+        "GB:nsl_dual" | "GB:motorway" => 70.0 * MPH,
+        _ => return None,
+    };
+    Some(MaxSpeed::Limit(speed))
+}
+
+/// Parses a `maxspeed:conditional` value like `30 @ (22:00-06:00); 80 @ wet`
+/// into limits and their conditions.
+pub fn parse_conditional(value: &str) -> Vec<(MaxSpeed, String)> {
+    let mut limits = Vec::new();
+    let mut rest = value;
+    while let Some((speed, tail)) = rest.split_once('@') {
+        let tail = tail.trim_start();
+        let (condition, next) = if let Some(inner) = tail.strip_prefix('(') {
+            match inner.split_once(')') {
+                Some((condition, next)) => (condition, next),
+                None => (inner, ""),
+            }
+        } else {
+            tail.split_once(';').unwrap_or((tail, ""))
+        };
+        if let Some(speed) = parse_maxspeed(speed.trim().trim_start_matches(';')) {
+            limits.push((speed, condition.trim().to_owned()));
+        }
+        rest = next;
+    }
+    limits
+}
+
+/// Whether a condition holds for the vehicle. Times and weather cannot be
+/// evaluated for a static graph, so only conditions on heavy vehicles ever
+/// hold.
+fn condition_holds(condition: &str, vehicle: Vehicle) -> bool {
+    vehicle == Vehicle::Hgv
+        && condition.split("AND").all(|c| {
+            let c = c.trim();
+            c == "hgv" || c == "trailer" || c.starts_with("weight>") || c.starts_with("weight >")
+        })
+}
+
+/// The speed limit of a way for the vehicle in the given direction. Without
+/// a direction the lower limit of both directions is returned.
+pub fn maxspeed(tags: &Tags, direction: Option<Direction>, vehicle: Vehicle) -> Option<MaxSpeed> {
+    let direction = match direction {
+        Some(direction) => direction,
+        None => {
+            return match (
+                maxspeed(tags, Some(Direction::Forward), vehicle),
+                maxspeed(tags, Some(Direction::Backward), vehicle),
+            ) {
+                (Some(f), Some(b)) => Some(f.min(b)),
+                (f, b) => f.or(b),
+            }
+        }
+    };
+    let suffix = match direction {
+        Direction::Forward => ":forward",
+        Direction::Backward => ":backward",
+    };
+    let prefixes: &[&str] = match vehicle {
+        Vehicle::Car => &["maxspeed"],
+        Vehicle::Hgv => &["maxspeed:hgv", "maxspeed"],
+    };
+    let get = |key: String| tags.get(key.as_str()).map(|v| v.as_str());
+
+    let mut limit = prefixes
+        .iter()
+        .flat_map(|p| [format!("{}{}", p, suffix), p.to_string()])
+        .find_map(|key| get(key).and_then(parse_maxspeed));
+    for prefix in prefixes {
+        for key in [
+            format!("{}{}:conditional", prefix, suffix),
+            format!("{}:conditional", prefix),
+        ] {
+            let conditional = get(key).map(parse_conditional).unwrap_or_default();
+            for (speed, condition) in conditional {
+                if condition_holds(&condition, vehicle) {
+                    limit = Some(limit.map_or(speed, |l| l.min(speed)));
+                }
+            }
+        }
+    }
+    limit
+}
+
+#[test]
+fn parse_values() {
+    assert_eq!(Some(MaxSpeed::Limit(50.0)), parse_maxspeed("50"));
+    assert_eq!(Some(MaxSpeed::Limit(50.0)), parse_maxspeed("50 km/h"));
+    assert_eq!(Some(MaxSpeed::Limit(30.0 * MPH)), parse_maxspeed("30 mph"));
+    assert_eq!(
+        Some(MaxSpeed::Limit(5.0 * KNOTS)),
+        parse_maxspeed("5 knots")
+    );
+    assert_eq!(Some(MaxSpeed::Limit(50.0)), parse_maxspeed("50;70"));
+    assert_eq!(Some(MaxSpeed::Unlimited), parse_maxspeed("none"));
+    assert_eq!(Some(MaxSpeed::Limit(80.0)), parse_maxspeed("FR:rural"));
+    assert_eq!(Some(MaxSpeed::Limit(100.0)), parse_maxspeed("AT:rural"));
+    assert_eq!(Some(MaxSpeed::Limit(130.0)), parse_maxspeed("RO:motorway"));
+    assert_eq!(Some(MaxSpeed::Limit(30.0)), parse_maxspeed("DE:zone:30"));
+    assert_eq!(Some(MaxSpeed::Limit(20.0)), parse_maxspeed("NL:zone20"));
+    assert_eq!(None, parse_maxspeed("signals"));
+    assert_eq!(None, parse_maxspeed("XX:rural"));
+    assert_eq!(None, parse_maxspeed("50;signals"));
+}
+
+#[test]
+fn conditional_values() {
+    let limits = parse_conditional("30 @ (22:00-06:00); 60 @ wet; 70 @ (weight>7.5 AND trailer)");
+    assert_eq!(
+        vec![
+            (MaxSpeed::Limit(30.0), "22:00-06:00".to_owned()),
+            (MaxSpeed::Limit(60.0), "wet".to_owned()),
+            (MaxSpeed::Limit(70.0), "weight>7.5 AND trailer".to_owned()),
+        ],
+        limits
+    );
+
+    let way = tags(&[
+        ("maxspeed", "100"),
+        (
+            "maxspeed:conditional",
+            "30 @ (22:00-06:00); 70 @ (weight>7.5)",
+        ),
+    ]);
+    assert_eq!(
+        Some(MaxSpeed::Limit(100.0)),
+        maxspeed(&way, None, Vehicle::Car)
+    );
+    assert_eq!(
+        Some(MaxSpeed::Limit(70.0)),
+        maxspeed(&way, None, Vehicle::Hgv)
+    );
+}
+
+#[test]
+fn directional_values() {
+    let way = tags(&[
+        ("maxspeed", "50"),
+        ("maxspeed:backward", "30"),
+        ("maxspeed:hgv:forward", "40"),
+    ]);
+    let car = |d| maxspeed(&way, d, Vehicle::Car);
+    let hgv = |d| maxspeed(&way, d, Vehicle::Hgv);
+    assert_eq!(Some(MaxSpeed::Limit(50.0)), car(Some(Direction::Forward)));
+    assert_eq!(Some(MaxSpeed::Limit(30.0)), car(Some(Direction::Backward)));
+    assert_eq!(Some(MaxSpeed::Limit(30.0)), car(None));
+    assert_eq!(Some(MaxSpeed::Limit(40.0)), hgv(Some(Direction::Forward)));
+    assert_eq!(Some(MaxSpeed::Limit(30.0)), hgv(Some(Direction::Backward)));
+}
@@ -15,14 +15,24 @@ GNU General Public License for more details.
 You should have received a copy of the GNU General Public License
 along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
+use super::access::{
+    access, access_with_key, directions, Access, Direction, Directions, ModeChain, BICYCLE, CAR,
+    FOOT, HGV,
+};
+use super::dimensions::VehicleDimensions;
+use super::maxspeed::{maxspeed, MaxSpeed, Vehicle};
 use super::pbf::{MetricIndices, Node};
+#[cfg(test)]
+use super::testing::tags;
 use super::units::*;
 
 use osmpbfreader::Tags;
 use rand::prelude::random;
+use serde::Deserialize;
 use smartstring::{LazyCompact, SmartString};
 
 use std::cell::RefCell;
+use std::fmt;
 use std::rc::Rc;
 
 #[derive(Debug)]
@@ -31,35 +41,143 @@ pub enum MetricError {
     NonFiniteTime(f64, f64),
 }
 
+impl fmt::Display for MetricError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MetricError::UnknownMetric => write!(f, "metric depends on an unknown metric"),
+            MetricError::NonFiniteTime(dist, speed) => write!(
+                f,
+                "travel time for distance {} and speed {} is not finite",
+                dist, speed
+            ),
+        }
+    }
+}
+
 pub type MetricResult<T> = Result<T, MetricError>;
 
+/// How the costs of consecutive edges are combined when they are merged
+/// into a single edge.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Aggregation {
+    Sum,
+    Max,
+    /// Mean of the values weighted by the length of the edges.
+    WeightedMean,
+}
+
+impl Aggregation {
+    pub fn combine(self, values: &[f64], lengths: &[f64]) -> f64 {
+        match self {
+            Aggregation::Sum => values.iter().sum(),
+            Aggregation::Max => values.iter().cloned().fold(f64::NEG_INFINITY, f64::max),
+            Aggregation::WeightedMean => {
+                let total: f64 = lengths.iter().sum();
+                if total > 0.0 {
+                    values.iter().zip(lengths).map(|(v, l)| v * l).sum::<f64>() / total
+                } else {
+                    values.iter().sum::<f64>() / values.len() as f64
+                }
+            }
+        }
+    }
+}
+
 pub trait Metric {
     fn name(&self) -> String;
+
+    /// Names of the metrics that have to be calculated before this one.
+    fn dependencies(&self) -> Vec<String> {
+        Vec::new()
+    }
+
+    fn aggregation(&self) -> Aggregation {
+        Aggregation::Sum
+    }
+
+    /// Whether the metric needs the heights of nodes, which are only looked
+    /// up in the elevation data if some metric does.
+    fn uses_height(&self) -> bool {
+        false
+    }
 }
 
 macro_rules! metric {
     ($t:ty) => {
+        metric!($t, Aggregation::Sum);
+    };
+    ($t:ty, $aggregation:expr) => {
+        impl Metric for $t {
+            fn name(&self) -> String {
+                stringify!($t).to_owned()
+            }
+
+            fn aggregation(&self) -> Aggregation {
+                $aggregation
+            }
+        }
+    };
+    ($t:ty, $aggregation:expr, uses_height) => {
         impl Metric for $t {
             fn name(&self) -> String {
                 stringify!($t).to_owned()
             }
+
+            fn aggregation(&self) -> Aggregation {
+                $aggregation
+            }
+
+            fn uses_height(&self) -> bool {
+                true
+            }
         }
     };
 }
 
 pub trait TagMetric<T>: Metric {
     fn calc(&self, tags: &Tags) -> MetricResult<T>;
+
+    /// The value for travelling along the way in `direction`. Only metrics
+    /// reading directional tags need to override this.
+    fn calc_directed(&self, tags: &Tags, _direction: Direction) -> MetricResult<T> {
+        self.calc(tags)
+    }
 }
 
 pub trait NodeMetric<T>: Metric {
     fn calc(&self, source: &Node, target: &Node) -> MetricResult<T>;
+
+    /// Calculates the metric along the elevation profile of an edge, which
+    /// starts at its source and ends at its target. Only metrics looking at
+    /// the heights in between need to override this.
+    fn calc_profile(&self, profile: &[Node]) -> MetricResult<T> {
+        self.calc(&profile[0], &profile[profile.len() - 1])
+    }
+}
+
+/// Sums a metric over all segments of an elevation profile.
+fn sum_profile<M: NodeMetric<Meters> + ?Sized>(
+    metric: &M,
+    profile: &[Node],
+) -> MetricResult<Meters> {
+    profile
+        .windows(2)
+        .map(|w| metric.calc(&w[0], &w[1]).map(|m| m.0))
+        .sum::<MetricResult<f64>>()
+        .map(Meters)
 }
 
 pub trait CostMetric<T>: Metric {
     fn calc(&self, costs: &[f64], map: &MetricIndices) -> MetricResult<T>;
 }
 
-fn bounded_speed(tags: &Tags, driver_max: f64) -> MetricResult<KilometersPerHour> {
+fn bounded_speed(
+    tags: &Tags,
+    driver_max: f64,
+    vehicle: Vehicle,
+    direction: Option<Direction>,
+) -> MetricResult<KilometersPerHour> {
     let street_type = tags.get("highway").map(smartstring::alias::String::as_ref);
     let tag_speed = match street_type {
         Some("motorway") | Some("trunk") => driver_max,
@@ -75,12 +193,21 @@ fn bounded_speed(tags: &Tags, driver_max: f64) -> MetricResult<KilometersPerHour
         _ => 50.0,
     };
 
-    let max_speed_tag = tags.get("maxspeed");
-    let max_speed = match max_speed_tag.map(smartstring::alias::String::as_ref) {
-        Some("none") => Some(driver_max),
-        Some("walk") | Some("DE:walk") => Some(10.0),
-        Some("living_street") | Some("DE:living_street") => Some(10.0),
-        Some(s) => s.parse().ok(),
+    limit_speed(tags, tag_speed, driver_max, vehicle, direction)
+}
+
+/// Applies the `maxspeed` tags to the speed derived from the street type.
+/// Without a direction the lower limit of both directions is used.
+pub fn limit_speed(
+    tags: &Tags,
+    tag_speed: f64,
+    driver_max: f64,
+    vehicle: Vehicle,
+    direction: Option<Direction>,
+) -> MetricResult<KilometersPerHour> {
+    let max_speed = match maxspeed(tags, direction, vehicle) {
+        Some(MaxSpeed::Unlimited) => Some(driver_max),
+        Some(MaxSpeed::Limit(s)) => Some(s),
         None => None,
     };
 
@@ -93,28 +220,40 @@ fn bounded_speed(tags: &Tags, driver_max: f64) -> MetricResult<KilometersPerHour
 
 #[allow(dead_code)]
 pub struct CarSpeed;
-metric!(CarSpeed);
+metric!(CarSpeed, Aggregation::WeightedMean);
 impl TagMetric<KilometersPerHour> for CarSpeed {
     fn calc(&self, tags: &Tags) -> MetricResult<KilometersPerHour> {
-        bounded_speed(tags, 120.0)
+        bounded_speed(tags, 120.0, Vehicle::Car, None)
+    }
+
+    fn calc_directed(&self, tags: &Tags, direction: Direction) -> MetricResult<KilometersPerHour> {
+        bounded_speed(tags, 120.0, Vehicle::Car, Some(direction))
     }
 }
 
 #[allow(dead_code)]
 pub struct TruckSpeed;
-metric!(TruckSpeed);
+metric!(TruckSpeed, Aggregation::WeightedMean);
 impl TagMetric<KilometersPerHour> for TruckSpeed {
     fn calc(&self, tags: &Tags) -> MetricResult<KilometersPerHour> {
-        bounded_speed(tags, 80.0)
+        bounded_speed(tags, 80.0, Vehicle::Hgv, None)
+    }
+
+    fn calc_directed(&self, tags: &Tags, direction: Direction) -> MetricResult<KilometersPerHour> {
+        bounded_speed(tags, 80.0, Vehicle::Hgv, Some(direction))
     }
 }
 
 #[allow(dead_code)]
 pub struct FastCarSpeed;
-metric!(FastCarSpeed);
+metric!(FastCarSpeed, Aggregation::WeightedMean);
 impl TagMetric<KilometersPerHour> for FastCarSpeed {
     fn calc(&self, tags: &Tags) -> MetricResult<KilometersPerHour> {
-        bounded_speed(tags, 180.0)
+        bounded_speed(tags, 180.0, Vehicle::Car, None)
+    }
+
+    fn calc_directed(&self, tags: &Tags, direction: Direction) -> MetricResult<KilometersPerHour> {
+        bounded_speed(tags, 180.0, Vehicle::Car, Some(direction))
     }
 }
 
@@ -137,15 +276,15 @@ impl NodeMetric<Meters> for Distance {
 }
 
 #[allow(dead_code)]
-pub struct TravelTime<D: Metric, S: Metric> {
+pub struct TravelTime<D: Metric + ?Sized, S: Metric + ?Sized> {
     distance: Rc<D>,
     speed: Rc<S>,
 }
 
 impl<D, S> Metric for TravelTime<D, S>
 where
-    D: Metric,
-    S: Metric,
+    D: Metric + ?Sized,
+    S: Metric + ?Sized,
 {
     fn name(&self) -> String {
         format!(
@@ -154,12 +293,16 @@ where
             self.speed.name()
         )
     }
+
+    fn dependencies(&self) -> Vec<String> {
+        vec![self.distance.name(), self.speed.name()]
+    }
 }
 
 impl<D, S> TravelTime<D, S>
 where
-    D: Metric,
-    S: Metric,
+    D: Metric + ?Sized,
+    S: Metric + ?Sized,
 {
     pub fn new(distance: Rc<D>, speed: Rc<S>) -> TravelTime<D, S> {
         TravelTime { distance, speed }
@@ -168,8 +311,8 @@ where
 
 impl<D, S> CostMetric<Seconds> for TravelTime<D, S>
 where
-    D: Metric,
-    S: Metric,
+    D: Metric + ?Sized,
+    S: Metric + ?Sized,
 {
     fn calc(&self, costs: &[f64], map: &MetricIndices) -> MetricResult<Seconds> {
         let dist_index = *map
@@ -207,6 +350,10 @@ where
     fn calc(&self, source: &Node, target: &Node) -> MetricResult<f64> {
         NodeMetric::<Meters>::calc(self, source, target).map(|c| c.0)
     }
+
+    fn calc_profile(&self, profile: &[Node]) -> MetricResult<f64> {
+        NodeMetric::<Meters>::calc_profile(self, profile).map(|c| c.0)
+    }
 }
 
 impl<T> TagMetric<f64> for T
@@ -216,33 +363,150 @@ where
     fn calc(&self, tags: &Tags) -> MetricResult<f64> {
         TagMetric::<KilometersPerHour>::calc(self, tags).map(|c| c.0)
     }
+
+    fn calc_directed(&self, tags: &Tags, direction: Direction) -> MetricResult<f64> {
+        TagMetric::<KilometersPerHour>::calc_directed(self, tags, direction).map(|c| c.0)
+    }
 }
 
 #[allow(dead_code)]
 pub struct HeightAscent;
-metric!(HeightAscent);
+metric!(HeightAscent, Aggregation::Sum, uses_height);
 
 impl NodeMetric<Meters> for HeightAscent {
     fn calc(&self, source: &Node, target: &Node) -> MetricResult<Meters> {
         let height_diff = target.height - source.height;
+        // unknown heights are NaN and count as no ascent
         if height_diff > 0.0 {
             Ok(Meters(height_diff))
         } else {
             Ok(Meters(0.0))
         }
     }
+
+    fn calc_profile(&self, profile: &[Node]) -> MetricResult<Meters> {
+        sum_profile(self, profile)
+    }
+}
+
+#[allow(dead_code)]
+pub struct HeightDescent;
+metric!(HeightDescent, Aggregation::Sum, uses_height);
+
+impl NodeMetric<Meters> for HeightDescent {
+    fn calc(&self, source: &Node, target: &Node) -> MetricResult<Meters> {
+        NodeMetric::<Meters>::calc(&HeightAscent, target, source)
+    }
+
+    fn calc_profile(&self, profile: &[Node]) -> MetricResult<Meters> {
+        sum_profile(self, profile)
+    }
+}
+
+/// Height difference in percent of the distance, negative downhill. Edges
+/// with unknown heights or without length are flat.
+fn gradient(source: &Node, target: &Node) -> f64 {
+    let distance = NodeMetric::<Meters>::calc(&Distance, source, target)
+        .map(|d| d.0)
+        .unwrap_or(0.0);
+    let height_diff = target.height - source.height;
+    if distance > 0.0 && height_diff.is_finite() {
+        height_diff / distance * 100.0
+    } else {
+        0.0
+    }
 }
 
+/// The mean gradient of an edge in percent.
 #[allow(dead_code)]
-pub struct UnsuitDistMetric<U, D> {
+pub struct Gradient;
+metric!(Gradient, Aggregation::WeightedMean, uses_height);
+
+impl NodeMetric<f64> for Gradient {
+    fn calc(&self, source: &Node, target: &Node) -> MetricResult<f64> {
+        Ok(gradient(source, target))
+    }
+}
+
+/// The steepest gradient of an edge in percent, which differs from
+/// `Gradient` once edges are merged.
+#[allow(dead_code)]
+pub struct MaxGradient;
+metric!(MaxGradient, Aggregation::Max, uses_height);
+
+impl NodeMetric<f64> for MaxGradient {
+    fn calc(&self, source: &Node, target: &Node) -> MetricResult<f64> {
+        Ok(gradient(source, target))
+    }
+
+    fn calc_profile(&self, profile: &[Node]) -> MetricResult<f64> {
+        Ok(profile
+            .windows(2)
+            .map(|w| gradient(&w[0], &w[1]))
+            .fold(f64::NEG_INFINITY, f64::max))
+    }
+}
+
+/// The ascent of an edge weighted by its steepness: climbs with the
+/// reference gradient count with their ascent, steeper ones with
+/// `(gradient / reference_gradient)^(exponent - 1)` times their ascent.
+/// Climbs between nodes at the same position have no gradient and count
+/// with their ascent. The exponent is expected to be at least 1, so that
+/// gentle climbs never cost more than steep ones.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClimbPenalty {
+    pub name: String,
+    /// Gradient in percent.
+    pub reference_gradient: f64,
+    pub exponent: f64,
+}
+
+impl Default for ClimbPenalty {
+    fn default() -> Self {
+        ClimbPenalty {
+            name: "ClimbPenalty".to_owned(),
+            reference_gradient: 5.0,
+            exponent: 2.0,
+        }
+    }
+}
+
+impl Metric for ClimbPenalty {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn uses_height(&self) -> bool {
+        true
+    }
+}
+
+impl NodeMetric<Meters> for ClimbPenalty {
+    fn calc(&self, source: &Node, target: &Node) -> MetricResult<Meters> {
+        let ascent = NodeMetric::<Meters>::calc(&HeightAscent, source, target)?;
+        let distance = NodeMetric::<Meters>::calc(&Distance, source, target)?;
+        if ascent.0 == 0.0 || distance.0 == 0.0 {
+            return Ok(ascent);
+        }
+        let steepness = gradient(source, target) / self.reference_gradient;
+        Ok(ascent * steepness.powf(self.exponent - 1.0))
+    }
+
+    fn calc_profile(&self, profile: &[Node]) -> MetricResult<Meters> {
+        sum_profile(self, profile)
+    }
+}
+
+#[allow(dead_code)]
+pub struct UnsuitDistMetric<U: ?Sized, D: ?Sized> {
     distance: Rc<D>,
     unsuitability: Rc<U>,
 }
 
 impl<U, D> Metric for UnsuitDistMetric<U, D>
 where
-    D: Metric,
-    U: Metric,
+    D: Metric + ?Sized,
+    U: Metric + ?Sized,
 {
     fn name(&self) -> String {
         format!(
@@ -251,12 +515,16 @@ where
             self.unsuitability.name()
         )
     }
+
+    fn dependencies(&self) -> Vec<String> {
+        vec![self.distance.name(), self.unsuitability.name()]
+    }
 }
 
 impl<D, U> UnsuitDistMetric<U, D>
 where
-    D: Metric,
-    U: Metric,
+    D: Metric + ?Sized,
+    U: Metric + ?Sized,
 {
     #[allow(dead_code)]
     pub fn new(distance: Rc<D>, unsuitability: Rc<U>) -> Self {
@@ -269,8 +537,8 @@ where
 
 impl<D, U> CostMetric<f64> for UnsuitDistMetric<U, D>
 where
-    D: Metric,
-    U: Metric,
+    D: Metric + ?Sized,
+    U: Metric + ?Sized,
 {
     fn calc(&self, costs: &[f64], map: &MetricIndices) -> MetricResult<f64> {
         let dist_index = *map
@@ -288,7 +556,7 @@ where
 
 #[allow(dead_code)]
 pub struct BicycleUnsuitability;
-metric!(BicycleUnsuitability);
+metric!(BicycleUnsuitability, Aggregation::WeightedMean);
 
 impl TagMetric<f64> for BicycleUnsuitability {
     fn calc(&self, tags: &Tags) -> MetricResult<f64> {
@@ -329,6 +597,235 @@ impl TagMetric<f64> for BicycleUnsuitability {
         };
         Ok(unsuitability)
     }
+
+    /// Cycle lanes on one side of the street only serve the direction of
+    /// the traffic on that side.
+    fn calc_directed(&self, tags: &Tags, direction: Direction) -> MetricResult<f64> {
+        let side = match direction {
+            Direction::Forward => "cycleway:right",
+            Direction::Backward => "cycleway:left",
+        };
+        let has_lane = [side, "cycleway:both"]
+            .iter()
+            .filter_map(|k| tags.get(*k))
+            .any(|v| v != "no" && v != "separate");
+        if has_lane {
+            return Ok(0.5);
+        }
+        self.calc(tags)
+    }
+}
+
+/// How much harder riding a way is than riding smooth asphalt, from the
+/// `surface`, `smoothness`, `tracktype` and `mtb:scale` tags. The worst of
+/// the tagged values decides; untagged tracks and paths count as unpaved.
+fn surface_factor(tags: &Tags) -> f64 {
+    let get = |key: &str| tags.get(key).map(smartstring::alias::String::as_ref);
+    let surface = get("surface").and_then(|surface| match surface {
+        "asphalt" | "concrete" | "concrete:plates" | "paved" => Some(1.0),
+        "paving_stones" | "metal" | "wood" | "concrete:lanes" => Some(1.2),
+        "compacted" | "fine_gravel" => Some(1.3),
+        "sett" | "unhewn_cobblestone" | "cobblestone" | "grass_paver" => Some(1.6),
+        "unpaved" | "gravel" | "pebblestone" | "ground" | "dirt" | "earth" => Some(1.8),
+        "grass" | "rock" | "woodchips" => Some(2.5),
+        "sand" | "mud" => Some(3.0),
+        _ => None,
+    });
+    let smoothness = get("smoothness").and_then(|smoothness| match smoothness {
+        "excellent" | "good" => Some(1.0),
+        "intermediate" => Some(1.3),
+        "bad" => Some(1.8),
+        "very_bad" => Some(2.5),
+        "horrible" => Some(3.5),
+        "very_horrible" | "impassable" => Some(5.0),
+        _ => None,
+    });
+    let tracktype = get("tracktype").and_then(|grade| match grade {
+        "grade1" => Some(1.0),
+        "grade2" => Some(1.4),
+        "grade3" => Some(1.8),
+        "grade4" => Some(2.2),
+        "grade5" => Some(2.8),
+        _ => None,
+    });
+    let mtb_scale = get("mtb:scale")
+        .and_then(|scale| scale.trim_end_matches(['+', '-']).parse::<u8>().ok())
+        .map(|scale| match scale {
+            0 => 1.5,
+            1 => 2.5,
+            2 => 3.5,
+            _ => 5.0,
+        });
+
+    let tagged = [surface, smoothness, tracktype, mtb_scale];
+    if tagged.iter().all(Option::is_none) {
+        return match get("highway") {
+            Some("track") => 1.8,
+            Some("path") | Some("bridleway") => 1.5,
+            _ => 1.0,
+        };
+    }
+    tagged
+        .iter()
+        .flatten()
+        .fold(1.0, |worst: f64, f| worst.max(*f))
+}
+
+/// Unsuitability of the surface of a way for cycling, 1 for smooth asphalt
+/// and larger for worse surfaces.
+#[allow(dead_code)]
+pub struct SurfaceQuality;
+metric!(SurfaceQuality, Aggregation::WeightedMean);
+
+impl TagMetric<f64> for SurfaceQuality {
+    fn calc(&self, tags: &Tags) -> MetricResult<f64> {
+        Ok(surface_factor(tags))
+    }
+}
+
+/// Cycling speed, 18 km/h on smooth surfaces and lower on gravel, sett or
+/// unpaved tracks.
+#[allow(dead_code)]
+pub struct BicycleSpeed;
+metric!(BicycleSpeed, Aggregation::WeightedMean);
+
+impl TagMetric<KilometersPerHour> for BicycleSpeed {
+    fn calc(&self, tags: &Tags) -> MetricResult<KilometersPerHour> {
+        let street_type = tags.get("highway").map(smartstring::alias::String::as_ref);
+        let dismount = tags
+            .get("bicycle")
+            .map(|b| b == "dismount")
+            .unwrap_or(false);
+        if street_type == Some("steps") {
+            return Ok(KilometersPerHour(2.0));
+        } else if dismount {
+            return Ok(KilometersPerHour(5.0));
+        }
+        Ok(KilometersPerHour(18.0 / surface_factor(tags)))
+    }
+}
+
+/// 1 on ways the mode given by the chain may only use to reach destinations
+/// on them (`destination`, `delivery`), 0 elsewhere.
+pub struct DestinationAccess {
+    name: &'static str,
+    chain: ModeChain,
+}
+
+impl DestinationAccess {
+    pub const CAR: DestinationAccess = DestinationAccess {
+        name: "CarDestinationAccess",
+        chain: CAR,
+    };
+    pub const TRUCK: DestinationAccess = DestinationAccess {
+        name: "TruckDestinationAccess",
+        chain: HGV,
+    };
+    pub const BICYCLE: DestinationAccess = DestinationAccess {
+        name: "BicycleDestinationAccess",
+        chain: BICYCLE,
+    };
+    pub const FOOT: DestinationAccess = DestinationAccess {
+        name: "FootDestinationAccess",
+        chain: FOOT,
+    };
+}
+
+impl Metric for DestinationAccess {
+    fn name(&self) -> String {
+        self.name.to_owned()
+    }
+
+    fn aggregation(&self) -> Aggregation {
+        Aggregation::WeightedMean
+    }
+}
+
+impl TagMetric<f64> for DestinationAccess {
+    fn calc(&self, tags: &Tags) -> MetricResult<f64> {
+        if access(tags, self.chain) == Some(Access::Destination) {
+            Ok(1.0)
+        } else {
+            Ok(0.0)
+        }
+    }
+}
+
+/// Whether a way has a sidewalk on at least one side.
+fn has_sidewalk(tags: &Tags) -> bool {
+    let tagged = |key: &str, no: &[&str]| {
+        tags.get(key)
+            .map(|v| !no.contains(&v.as_str()))
+            .unwrap_or(false)
+    };
+    tagged("sidewalk", &["no", "none", "separate"])
+        || ["sidewalk:left", "sidewalk:right", "sidewalk:both"]
+            .iter()
+            .any(|k| tagged(k, &["no", "separate"]))
+}
+
+#[allow(dead_code)]
+pub struct WalkingSpeed;
+metric!(WalkingSpeed, Aggregation::WeightedMean);
+
+impl TagMetric<KilometersPerHour> for WalkingSpeed {
+    fn calc(&self, tags: &Tags) -> MetricResult<KilometersPerHour> {
+        let street_type = tags.get("highway").map(smartstring::alias::String::as_ref);
+        let speed: f64 = match street_type {
+            // climbing steps is much slower than walking
+            Some("steps") => 2.0,
+            Some("path") | Some("track") | Some("bridleway") => 4.5,
+            _ => 5.0,
+        };
+        let speed = match tags
+            .get("sac_scale")
+            .map(smartstring::alias::String::as_ref)
+        {
+            Some("mountain_hiking") => speed.min(3.0),
+            Some("demanding_mountain_hiking") | Some("alpine_hiking") => speed.min(2.0),
+            Some(s) if s.contains("alpine") => speed.min(1.0),
+            _ => speed,
+        };
+        Ok(KilometersPerHour(speed))
+    }
+}
+
+#[allow(dead_code)]
+pub struct PedestrianUnsuitability;
+metric!(PedestrianUnsuitability, Aggregation::WeightedMean);
+
+impl TagMetric<f64> for PedestrianUnsuitability {
+    fn calc(&self, tags: &Tags) -> MetricResult<f64> {
+        let street_type = tags.get("highway").map(smartstring::alias::String::as_ref);
+        if tags
+            .get("footway")
+            .map(|f| f == "crossing")
+            .unwrap_or(false)
+        {
+            // crossing a road, the less protected the worse
+            let crossing = tags.get("crossing").map(smartstring::alias::String::as_ref);
+            return Ok(match crossing {
+                Some("traffic_signals") => 1.5,
+                Some("unmarked") => 3.0,
+                _ => 2.0,
+            });
+        }
+        let unsuitability: f64 = match street_type {
+            Some("pedestrian") | Some("footway") | Some("living_street") => 1.0,
+            Some("path") | Some("track") | Some("corridor") | Some("platform") => 1.5,
+            Some("steps") => 3.0,
+            Some("cycleway") | Some("bridleway") => 2.5,
+            Some("residential") | Some("service") => 2.0,
+            Some("unclassified") | Some("road") => 3.0,
+            Some("tertiary") | Some("tertiary_link") => 4.0,
+            Some("secondary") | Some("secondary_link") => 5.0,
+            _ => 6.0,
+        };
+        if has_sidewalk(tags) {
+            return Ok(unsuitability.min(1.5));
+        }
+        Ok(unsuitability)
+    }
 }
 
 #[allow(dead_code)]
@@ -387,7 +884,7 @@ pub struct GridX(pub Rc<RefCell<Grid>>);
 metric!(GridX);
 impl NodeMetric<f64> for GridX {
     fn calc(&self, a: &Node, _: &Node) -> MetricResult<f64> {
-        if self.0.borrow().index(a).x % 2 == 0 {
+        if self.0.borrow().index(a).x.is_multiple_of(2) {
             Ok(20.0)
         } else {
             Ok(1.0)
@@ -399,7 +896,7 @@ pub struct GridY(pub Rc<RefCell<Grid>>);
 metric!(GridY);
 impl NodeMetric<f64> for GridY {
     fn calc(&self, a: &Node, _: &Node) -> MetricResult<f64> {
-        if self.0.borrow().index(a).y % 2 == 0 {
+        if self.0.borrow().index(a).y.is_multiple_of(2) {
             Ok(20.0)
         } else {
             Ok(1.0)
@@ -412,7 +909,7 @@ metric!(ChessBoard);
 impl NodeMetric<f64> for ChessBoard {
     fn calc(&self, a: &Node, _: &Node) -> MetricResult<f64> {
         let c = self.0.borrow().index(a);
-        if c.y % 2 == 0 && c.x % 2 == 0 {
+        if c.y.is_multiple_of(2) && c.x.is_multiple_of(2) {
             Ok(20.0)
         } else {
             Ok(1.0)
@@ -430,6 +927,35 @@ impl TagMetric<f64> for RandomWeights {
 
 pub trait EdgeFilter {
     fn is_invalid(&self, tags: &Tags) -> bool;
+
+    /// The directions in which a valid way can be used.
+    fn directions(&self, tags: &Tags) -> Directions {
+        directions(tags, None)
+    }
+}
+
+impl<F: EdgeFilter + ?Sized> EdgeFilter for Box<F> {
+    fn is_invalid(&self, tags: &Tags) -> bool {
+        (**self).is_invalid(tags)
+    }
+
+    fn directions(&self, tags: &Tags) -> Directions {
+        (**self).directions(tags)
+    }
+}
+
+/// Whether a way has a cycle lane against the direction of a one-way
+/// street, e.g. `cycleway=opposite_lane`.
+pub fn has_contraflow_lane(tags: &Tags) -> bool {
+    [
+        "cycleway",
+        "cycleway:left",
+        "cycleway:right",
+        "cycleway:both",
+    ]
+    .iter()
+    .filter_map(|k| tags.get(*k))
+    .any(|v| v.starts_with("opposite"))
 }
 
 #[allow(dead_code)]
@@ -437,13 +963,12 @@ pub struct BicycleEdgeFilter;
 
 impl EdgeFilter for BicycleEdgeFilter {
     fn is_invalid(&self, tags: &Tags) -> bool {
-        let bicycle_tag = tags.get("bicycle");
-        if bicycle_tag == Some(&SmartString::<LazyCompact>::from("no")) {
-            return true;
+        match access_with_key(tags, BICYCLE) {
+            Some((_, Access::Forbidden)) => return true,
+            Some(("bicycle", _)) => return false,
+            _ => {}
         }
-        if tags.get("cycleway").is_some()
-            || bicycle_tag.is_some() && bicycle_tag != Some(&SmartString::<LazyCompact>::from("no"))
-        {
+        if tags.get("cycleway").is_some() {
             return false;
         }
 
@@ -473,31 +998,128 @@ impl EdgeFilter for BicycleEdgeFilter {
                 | None
         )
     }
+
+    /// `oneway:bicycle` and contraflow cycle lanes like `cycleway=opposite`
+    /// open one-way streets in both directions.
+    fn directions(&self, tags: &Tags) -> Directions {
+        if has_contraflow_lane(tags) && tags.get("oneway:bicycle").is_none() {
+            return Directions::BOTH;
+        }
+        directions(tags, Some("bicycle"))
+    }
 }
 #[allow(dead_code)]
 pub struct CarEdgeFilter;
 
 impl EdgeFilter for CarEdgeFilter {
     fn is_invalid(&self, tags: &Tags) -> bool {
+        access(tags, CAR) == Some(Access::Forbidden) || is_closed_to_motor_vehicles(tags)
+    }
+
+    fn directions(&self, tags: &Tags) -> Directions {
+        directions(tags, Some("motor_vehicle"))
+    }
+}
+
+/// Edge filter for heavy goods vehicles. Besides `hgv` access, ways with
+/// limits below the dimensions of the truck, e.g. `maxweight`, are invalid.
+#[allow(dead_code)]
+#[derive(Debug, Default)]
+pub struct TruckEdgeFilter {
+    pub dimensions: VehicleDimensions,
+}
+
+impl EdgeFilter for TruckEdgeFilter {
+    fn is_invalid(&self, tags: &Tags) -> bool {
+        access(tags, HGV) == Some(Access::Forbidden)
+            || is_closed_to_motor_vehicles(tags)
+            || self.dimensions.exceed_limits(tags)
+    }
+
+    fn directions(&self, tags: &Tags) -> Directions {
+        directions(tags, Some("motor_vehicle"))
+    }
+}
+
+/// Wraps another edge filter, e.g. the one of a profile file, and
+/// additionally drops ways with limits below the dimensions of the vehicle.
+pub struct DimensionFilter<F> {
+    pub filter: F,
+    pub dimensions: VehicleDimensions,
+}
+
+impl<F: EdgeFilter> EdgeFilter for DimensionFilter<F> {
+    fn is_invalid(&self, tags: &Tags) -> bool {
+        self.filter.is_invalid(tags) || self.dimensions.exceed_limits(tags)
+    }
+
+    fn directions(&self, tags: &Tags) -> Directions {
+        self.filter.directions(tags)
+    }
+}
+
+#[allow(dead_code)]
+pub struct FootEdgeFilter;
+
+impl EdgeFilter for FootEdgeFilter {
+    fn is_invalid(&self, tags: &Tags) -> bool {
+        match access_with_key(tags, FOOT) {
+            Some((_, Access::Forbidden)) => return true,
+            Some(("foot", _)) => return false,
+            _ => {}
+        }
         let street_type = tags.get("highway").map(smartstring::alias::String::as_ref);
+        // areas are only walkable if they are pedestrian zones
+        if tags.get("area").map(|a| a == "yes").unwrap_or(false) {
+            return !matches!(street_type, Some("pedestrian") | Some("footway"));
+        }
+        if has_sidewalk(tags) {
+            return false;
+        }
         matches!(
             street_type,
-            Some("footway")
-                | Some("bridleway")
-                | Some("steps")
-                | Some("path")
-                | Some("cycleway")
-                | Some("track")
+            Some("motorway")
+                | Some("motorway_link")
+                | Some("trunk")
+                | Some("trunk_link")
                 | Some("proposed")
                 | Some("construction")
-                | Some("pedestrian")
-                | Some("rest_area")
-                | Some("elevator")
                 | Some("raceway")
-                | Some("service")
+                | Some("bus_guideway")
+                | Some("rest_area")
                 | None
         )
     }
+
+    /// One-way streets can be walked in both directions, unless
+    /// `oneway:foot` says otherwise.
+    fn directions(&self, tags: &Tags) -> Directions {
+        tags.get("oneway:foot")
+            .and_then(|v| Directions::from_oneway(v))
+            .unwrap_or(Directions::BOTH)
+    }
+}
+
+/// Whether the street type is not meant for motor vehicles.
+fn is_closed_to_motor_vehicles(tags: &Tags) -> bool {
+    let street_type = tags.get("highway").map(smartstring::alias::String::as_ref);
+    matches!(
+        street_type,
+        Some("footway")
+            | Some("bridleway")
+            | Some("steps")
+            | Some("path")
+            | Some("cycleway")
+            | Some("track")
+            | Some("proposed")
+            | Some("construction")
+            | Some("pedestrian")
+            | Some("rest_area")
+            | Some("elevator")
+            | Some("raceway")
+            | Some("service")
+            | None
+    )
 }
 
 #[test]
@@ -541,3 +1163,203 @@ fn index_for_negative_coords() {
     assert_eq!(6, c.x);
     assert_eq!(12, c.y);
 }
+
+#[cfg(test)]
+fn hill(height_diff: f64) -> (Node, Node) {
+    // about 111 m apart
+    (
+        Node::new(0, 48.0, 9.0, 300.0),
+        Node::new(1, 48.001, 9.0, 300.0 + height_diff),
+    )
+}
+
+#[test]
+fn ascent_and_descent() {
+    let (low, high) = hill(10.0);
+    let ascent = |s, t| NodeMetric::<f64>::calc(&HeightAscent, s, t).unwrap();
+    let descent = |s, t| NodeMetric::<f64>::calc(&HeightDescent, s, t).unwrap();
+    assert_eq!(10.0, ascent(&low, &high));
+    assert_eq!(0.0, descent(&low, &high));
+    assert_eq!(0.0, ascent(&high, &low));
+    assert_eq!(10.0, descent(&high, &low));
+
+    let unknown = Node::new(2, 48.001, 9.0, f64::NAN);
+    assert_eq!(0.0, descent(&unknown, &low));
+}
+
+#[test]
+fn gradients() {
+    let (low, high) = hill(11.1195);
+    let up = NodeMetric::<f64>::calc(&Gradient, &low, &high).unwrap();
+    let down = NodeMetric::<f64>::calc(&MaxGradient, &high, &low).unwrap();
+    assert!((up - 10.0).abs() < 1e-3);
+    assert!((down + 10.0).abs() < 1e-3);
+    assert_eq!(0.0, NodeMetric::<f64>::calc(&Gradient, &low, &low).unwrap());
+}
+
+#[test]
+fn climb_penalty_grows_with_steepness() {
+    let penalty = ClimbPenalty::default();
+    let calc = |d: f64| {
+        let (low, high) = hill(d);
+        NodeMetric::<f64>::calc(&penalty, &low, &high).unwrap()
+    };
+    // 5 % gradient counts with its ascent, 10 % twice as much
+    assert!((calc(5.559_75) - 5.559_75).abs() < 1e-3);
+    assert!((calc(11.1195) - 22.239).abs() < 1e-2);
+    assert!(calc(2.0) < 2.0);
+    assert_eq!(0.0, calc(-10.0));
+
+    let linear = ClimbPenalty {
+        exponent: 1.0,
+        ..ClimbPenalty::default()
+    };
+    let (low, high) = hill(11.1195);
+    assert!((NodeMetric::<f64>::calc(&linear, &low, &high).unwrap() - 11.1195).abs() < 1e-9);
+}
+
+#[test]
+fn climb_penalty_of_coincident_nodes() {
+    let low = Node::new(0, 48.0, 9.0, 300.0);
+    let high = Node::new(1, 48.0, 9.0, 310.0);
+    for exponent in [1.0, 2.0, 3.0] {
+        let penalty = ClimbPenalty {
+            exponent,
+            ..ClimbPenalty::default()
+        };
+        assert_eq!(
+            10.0,
+            NodeMetric::<f64>::calc(&penalty, &low, &high).unwrap()
+        );
+        assert_eq!(0.0, NodeMetric::<f64>::calc(&penalty, &high, &low).unwrap());
+    }
+}
+
+#[test]
+fn height_metrics_along_profile() {
+    // crosses a 20 m hill between two nodes of the same height
+    let profile: Vec<Node> = [300.0, 310.0, 320.0, 305.0, 300.0]
+        .iter()
+        .enumerate()
+        .map(|(i, h)| Node::new(0, 48.0 + i as f64 * 0.0003, 9.0, *h))
+        .collect();
+    let (first, last) = (&profile[0], &profile[4]);
+    assert_eq!(
+        0.0,
+        NodeMetric::<f64>::calc(&HeightAscent, first, last).unwrap()
+    );
+    assert_eq!(
+        20.0,
+        NodeMetric::<f64>::calc_profile(&HeightAscent, &profile).unwrap()
+    );
+    assert_eq!(
+        20.0,
+        NodeMetric::<f64>::calc_profile(&HeightDescent, &profile).unwrap()
+    );
+    assert_eq!(
+        0.0,
+        NodeMetric::<f64>::calc_profile(&Gradient, &profile).unwrap()
+    );
+    let max = NodeMetric::<f64>::calc_profile(&MaxGradient, &profile).unwrap();
+    assert!((max - 29.98).abs() < 0.01);
+}
+
+#[test]
+fn directed_tag_metrics_and_one_ways() {
+    let way = tags(&[
+        ("highway", "primary"),
+        ("maxspeed", "70"),
+        ("maxspeed:backward", "50"),
+        ("cycleway:left", "lane"),
+    ]);
+    let speed = |d| TagMetric::<f64>::calc_directed(&CarSpeed, &way, d).unwrap();
+    assert_eq!(70.0, speed(Direction::Forward));
+    assert_eq!(50.0, speed(Direction::Backward));
+    let unsuitability = |d| BicycleUnsuitability.calc_directed(&way, d).unwrap();
+    assert_eq!(5.0, unsuitability(Direction::Forward));
+    assert_eq!(0.5, unsuitability(Direction::Backward));
+
+    let contraflow = tags(&[
+        ("highway", "residential"),
+        ("oneway", "yes"),
+        ("cycleway:left", "opposite_lane"),
+    ]);
+    assert_eq!(Directions::FORWARD, CarEdgeFilter.directions(&contraflow));
+    assert_eq!(Directions::BOTH, BicycleEdgeFilter.directions(&contraflow));
+}
+
+#[test]
+fn foot_profile() {
+    let filter = |pairs: &[(&str, &str)]| FootEdgeFilter.is_invalid(&tags(pairs));
+    assert!(!filter(&[("highway", "footway")]));
+    assert!(!filter(&[("highway", "residential"), ("oneway", "yes")]));
+    assert!(filter(&[("highway", "trunk")]));
+    assert!(!filter(&[("highway", "trunk"), ("sidewalk", "right")]));
+    assert!(filter(&[("highway", "trunk"), ("sidewalk", "separate")]));
+    assert!(filter(&[("highway", "footway"), ("foot", "no")]));
+    assert!(!filter(&[
+        ("highway", "motorway"),
+        ("access", "no"),
+        ("foot", "yes")
+    ]));
+    assert!(!filter(&[("highway", "pedestrian"), ("area", "yes")]));
+    assert!(filter(&[("highway", "service"), ("area", "yes")]));
+    assert_eq!(
+        Directions::BOTH,
+        FootEdgeFilter.directions(&tags(&[("highway", "residential"), ("oneway", "yes")]))
+    );
+
+    let speed =
+        |pairs: &[(&str, &str)]| TagMetric::<f64>::calc(&WalkingSpeed, &tags(pairs)).unwrap();
+    assert_eq!(5.0, speed(&[("highway", "footway")]));
+    assert_eq!(2.0, speed(&[("highway", "steps")]));
+
+    let unsuitability =
+        |pairs: &[(&str, &str)]| PedestrianUnsuitability.calc(&tags(pairs)).unwrap();
+    assert_eq!(3.0, unsuitability(&[("highway", "steps")]));
+    assert_eq!(6.0, unsuitability(&[("highway", "primary")]));
+    assert_eq!(
+        1.5,
+        unsuitability(&[("highway", "primary"), ("sidewalk", "both")])
+    );
+    assert_eq!(
+        3.0,
+        unsuitability(&[
+            ("highway", "footway"),
+            ("footway", "crossing"),
+            ("crossing", "unmarked")
+        ])
+    );
+}
+
+#[test]
+fn surface_metrics() {
+    let quality = |pairs: &[(&str, &str)]| SurfaceQuality.calc(&tags(pairs)).unwrap();
+    let speed =
+        |pairs: &[(&str, &str)]| TagMetric::<f64>::calc(&BicycleSpeed, &tags(pairs)).unwrap();
+    assert_eq!(1.0, quality(&[("highway", "residential")]));
+    assert_eq!(1.0, quality(&[("surface", "asphalt")]));
+    assert_eq!(1.6, quality(&[("surface", "sett")]));
+    assert_eq!(1.8, quality(&[("highway", "track")]));
+    assert_eq!(
+        1.3,
+        quality(&[
+            ("highway", "track"),
+            ("tracktype", "grade1"),
+            ("surface", "compacted")
+        ])
+    );
+    // the worst tag decides
+    assert_eq!(
+        2.5,
+        quality(&[("surface", "asphalt"), ("smoothness", "very_bad")])
+    );
+    assert_eq!(3.5, quality(&[("highway", "path"), ("mtb:scale", "2+")]));
+
+    assert_eq!(
+        18.0,
+        speed(&[("highway", "cycleway"), ("surface", "asphalt")])
+    );
+    assert_eq!(10.0, speed(&[("highway", "track"), ("surface", "gravel")]));
+    assert_eq!(2.0, speed(&[("highway", "steps")]));
+}
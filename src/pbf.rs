@@ -17,10 +17,17 @@ along with this program.  If not, see <https://www.gnu.org/licenses/>.
  */
 use osmpbfreader::{OsmObj, OsmPbfReader, Way};
 
+use super::access::Direction;
+use super::area::Area;
+use super::components::*;
+use super::contraction::contract_chains;
+use super::elevation::{sample_profile, ElevationSource, Smoothing};
 use super::metrics::*;
+use super::restrictions::*;
 use std::cell::RefCell;
 use std::cmp::Ordering;
-use std::collections::{BTreeMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt;
 use std::fs::File;
 use std::rc::Rc;
 use std::sync::mpsc::{channel, Receiver, Sender};
@@ -31,101 +38,242 @@ pub type NodeMetrics = Vec<Rc<dyn NodeMetric<f64>>>;
 pub type CostMetrics = Vec<Rc<dyn CostMetric<f64>>>;
 pub type InternalMetrics = HashSet<String>;
 pub type MetricIndices = BTreeMap<String, usize>;
+pub type Graph = (Vec<Node>, Vec<Edge>, Vec<TurnRestriction>);
+/// Elevation profiles of edges by way and the nodes of the edge in the
+/// direction of the way.
+type EdgeProfiles = HashMap<(OsmWayId, OsmNodeId, OsmNodeId), Vec<Node>>;
+
+#[derive(Debug)]
+pub enum ExtractError {
+    /// An I/O error while accessing the given file.
+    Io(String, std::io::Error),
+    Pbf(String, osmpbfreader::Error),
+    /// No SRTM tile with the given name covers latitude and longitude.
+    MissingSrtmTile(String, Latitude, Longitude),
+    /// The elevation data in the given file cannot be used.
+    InvalidElevationData(String, String),
+    /// The elevation data in the given file does not cover latitude and longitude.
+    OutsideElevationData(String, Latitude, Longitude),
+    MissingNode {
+        way: OsmWayId,
+        node: OsmNodeId,
+    },
+    /// The thread collecting the ids of the nodes used by ways stopped.
+    NodeIdCollection,
+    /// Calculating the named metric failed for a way.
+    Metric {
+        metric: String,
+        way: OsmWayId,
+        error: MetricError,
+    },
+}
+
+impl fmt::Display for ExtractError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExtractError::Io(path, e) => write!(f, "{}: {}", path, e),
+            ExtractError::Pbf(path, e) => write!(f, "{}: could not decode pbf data: {}", path, e),
+            ExtractError::MissingSrtmTile(file, lat, lng) => write!(
+                f,
+                "could not find srtm file {} for lat: {}, lng: {}",
+                file, lat, lng
+            ),
+            ExtractError::InvalidElevationData(file, reason) => {
+                write!(f, "invalid elevation data {}: {}", file, reason)
+            }
+            ExtractError::OutsideElevationData(file, lat, lng) => write!(
+                f,
+                "elevation data {} does not cover lat: {}, lng: {}",
+                file, lat, lng
+            ),
+            ExtractError::MissingNode { way, node } => write!(
+                f,
+                "way {} references node {}, which is not part of the extract",
+                way, node
+            ),
+            ExtractError::NodeIdCollection => {
+                write!(f, "could not collect the ids of the nodes used by ways")
+            }
+            ExtractError::Metric { metric, way, error } => {
+                write!(
+                    f,
+                    "could not calculate {} for way {}: {}",
+                    metric, way, error
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for ExtractError {}
+
+/// What to do with ways referencing nodes that are not part of the pbf file.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum MissingNodes {
+    /// Stop the extraction with an error.
+    #[default]
+    Abort,
+    /// Drop all edges of the affected ways.
+    DropWay,
+    /// Drop the edges touching a missing node and keep the rest of the way.
+    Split,
+}
+
+/// Optional steps of the extraction.
+#[derive(Default)]
+pub struct LoaderOptions {
+    /// Resolve `type=restriction` relations to edge indices.
+    pub turn_restrictions: bool,
+    /// Access keys of the transport mode, restrictions not applying to it
+    /// are ignored. `None` uses every restriction.
+    pub restriction_modes: Option<Vec<String>>,
+    /// Only keep nodes inside this area and edges between them.
+    pub area: Option<Area>,
+    /// Which strongly connected components to keep.
+    pub components: ComponentFilter,
+    /// Merge chains of degree-2 nodes into single edges.
+    pub simplify: bool,
+    /// Keep the coordinates of nodes removed by `simplify` as edge geometry.
+    pub keep_geometry: bool,
+    pub missing_nodes: MissingNodes,
+    /// Sample the elevation data every this many meters along edges
+    /// instead of using only the heights of their endpoints.
+    pub elevation_spacing: Option<f64>,
+    /// Smooth the heights along each way before calculating node metrics.
+    pub smoothing: Option<Smoothing>,
+}
 
 pub struct Loader<'a, Filter: EdgeFilter> {
     pbf_path: &'a str,
-    srtm_path: &'a str,
+    elevation: Option<Box<dyn ElevationSource>>,
     edge_filter: Filter,
     tag_metrics: TagMetrics,
     node_metrics: NodeMetrics,
     cost_metrics: CostMetrics,
     pub internal_metrics: InternalMetrics,
     pub metrics_indices: MetricIndices,
+    aggregations: Vec<Aggregation>,
     grid: Rc<RefCell<Grid>>,
+    options: LoaderOptions,
 }
 
 #[allow(clippy::too_many_arguments)]
 impl<'a, Filter: EdgeFilter> Loader<'a, Filter> {
     pub fn new(
         pbf_path: &'a str,
-        srtm_path: &'a str,
+        elevation: Option<Box<dyn ElevationSource>>,
         edge_filter: Filter,
         tag_metrics: TagMetrics,
         node_metrics: NodeMetrics,
         cost_metrics: CostMetrics,
         internal_metrics: InternalMetrics,
         grid: Rc<RefCell<Grid>>,
+        options: LoaderOptions,
     ) -> Loader<'a, Filter> {
         let mut metrics_indices: MetricIndices = BTreeMap::new();
+        let mut aggregations = Vec::new();
         let mut index = 0;
         for t in &tag_metrics {
             metrics_indices.insert(t.name(), index);
+            aggregations.push(t.aggregation());
             index += 1;
         }
         for n in &node_metrics {
             metrics_indices.insert(n.name(), index);
+            aggregations.push(n.aggregation());
             index += 1;
         }
         for c in &cost_metrics {
             metrics_indices.insert(c.name(), index);
+            aggregations.push(c.aggregation());
             index += 1;
         }
         Loader {
             pbf_path,
-            srtm_path,
+            elevation,
             edge_filter,
             tag_metrics,
             node_metrics,
             cost_metrics,
             internal_metrics,
             metrics_indices,
+            aggregations,
             grid,
+            options,
         }
     }
 
     /// Loads the graph from a pbf file.
-    pub fn load_graph(&self) -> (Vec<Node>, Vec<Edge>) {
+    pub fn load_graph(&self) -> Result<Graph, ExtractError> {
         println!("Extracting data out of: {}", self.pbf_path);
-        let fs = File::open(self.pbf_path).unwrap();
+        let fs =
+            File::open(self.pbf_path).map_err(|e| ExtractError::Io(self.pbf_path.to_owned(), e))?;
         let mut reader = OsmPbfReader::new(fs);
+        let pbf_error = |e| ExtractError::Pbf(self.pbf_path.to_owned(), e);
 
         let (id_sender, id_receiver) = channel();
         let set_receiver = self.collect_node_ids(id_receiver);
 
-        let mut edges: Vec<Edge> = reader
-            .par_iter()
-            .flat_map(|obj| {
-                if let Ok(OsmObj::Way(w)) = obj {
-                    self.process_way(&w, &id_sender)
-                } else {
-                    Vec::new()
+        let mut raw_restrictions = Vec::new();
+        let mut edges: Vec<Edge> = Vec::new();
+        let mut ways: Vec<(OsmWayId, Vec<OsmNodeId>)> = Vec::new();
+        for obj in reader.par_iter() {
+            match obj.map_err(pbf_error)? {
+                OsmObj::Way(w) => {
+                    let way_edges = self.process_way(&w, &id_sender)?;
+                    if self.options.smoothing.is_some() && !way_edges.is_empty() {
+                        let way_nodes = w.nodes.iter().map(|n| n.0 as OsmNodeId).collect();
+                        ways.push((w.id.0 as OsmWayId, way_nodes));
+                    }
+                    edges.extend(way_edges);
                 }
-            })
-            .collect();
+                OsmObj::Relation(r) if self.options.turn_restrictions => {
+                    raw_restrictions.extend(RawRestriction::from_relation(
+                        &r,
+                        self.options.restriction_modes.as_deref(),
+                    ));
+                }
+                _ => {}
+            }
+        }
         println!("Collected {} edges", edges.len());
-        reader.rewind().expect("Can't rewind pbf file!");
+        if self.options.turn_restrictions {
+            println!("Collected {} turn restrictions", raw_restrictions.len());
+        }
+        reader.rewind().map_err(pbf_error)?;
         drop(id_sender);
 
-        let id_set = set_receiver.recv().expect("Did not get node ids");
-        let srtm = self.metrics_indices.contains_key(&HeightAscent.name());
-
-        let mut nodes: Vec<Node> = reader
-            .par_iter()
-            .filter_map(|obj| {
-                if let Ok(OsmObj::Node(n)) = obj {
-                    if id_set.contains(&n.id) {
-                        let lat = f64::from(n.decimicro_lat) / 10_000_000.0;
-                        let lng = f64::from(n.decimicro_lon) / 10_000_000.0;
-                        let height = if srtm { self.srtm(lat, lng) } else { 0.0 };
-                        Some(Node::new(n.id.0 as usize, lat, lng, height))
-                    } else {
-                        None
-                    }
-                } else {
-                    None
+        let id_set = set_receiver
+            .recv()
+            .map_err(|_| ExtractError::NodeIdCollection)?;
+
+        let mut nodes: Vec<Node> = Vec::new();
+        let mut found: HashSet<OsmNodeId> = HashSet::new();
+        for obj in reader.par_iter() {
+            if let OsmObj::Node(n) = obj.map_err(pbf_error)? {
+                if !id_set.contains(&n.id) {
+                    continue;
                 }
-            })
-            .collect();
+                found.insert(n.id.0 as OsmNodeId);
+                let lat = f64::from(n.decimicro_lat) / 10_000_000.0;
+                let lng = f64::from(n.decimicro_lon) / 10_000_000.0;
+                if !self.is_inside_area(lat, lng) {
+                    continue;
+                }
+                let height = match &self.elevation {
+                    Some(elevation) => elevation.height(lat, lng)?,
+                    None => 0.0,
+                };
+                nodes.push(Node::new(n.id.0 as usize, lat, lng, height));
+            }
+        }
+        let dropped = self.handle_missing_nodes(&found, &mut edges)?;
+        if self.options.area.is_some() || dropped {
+            self.delete_clipped_edges(&mut nodes, &mut edges);
+        }
+        if self.options.area.is_some() {
+            println!("{} edges inside area", edges.len());
+        }
         {
             let mut grid = (*self.grid).borrow_mut();
             nodes.iter().for_each(|n| grid.add(n));
@@ -135,8 +283,11 @@ impl<'a, Filter: EdgeFilter> Loader<'a, Filter> {
 
         println!("Calculating Metrics");
 
-        self.rename_node_ids_and_calculate_node_metrics(&mut nodes, &mut edges);
-        self.calculate_cost_metrics(&mut edges);
+        let profiles = self.way_profiles(&nodes, &ways)?;
+        drop(ways);
+        self.rename_node_ids_and_calculate_node_metrics(&mut nodes, &mut edges, &profiles)?;
+        drop(profiles);
+        self.calculate_cost_metrics(&mut edges)?;
 
         println!("Deleting duplicate and dominated edges");
 
@@ -144,8 +295,63 @@ impl<'a, Filter: EdgeFilter> Loader<'a, Filter> {
         edges = self.delete_dominated_edges(edges);
 
         println!("{} edges left", edges.len());
-        (nodes, edges)
+
+        if self.options.components != ComponentFilter::All {
+            let keep = nodes_to_keep(nodes.len(), &edges, self.options.components);
+            let (n, e) = retain_nodes(nodes, edges, &keep);
+            nodes = n;
+            edges = e;
+            println!(
+                "{} nodes and {} edges left in kept components",
+                nodes.len(),
+                edges.len()
+            );
+        }
+
+        if self.options.simplify {
+            let node_ids = Self::node_ids(&nodes);
+            let via_nodes: HashSet<NodeId> = raw_restrictions
+                .iter()
+                .filter_map(|r| r.via_node().and_then(|n| node_ids.get(&n)).copied())
+                .collect();
+            let (n, e) = contract_chains(
+                nodes,
+                edges,
+                &self.aggregations,
+                &via_nodes,
+                self.options.turn_restrictions,
+                self.options.keep_geometry,
+            );
+            nodes = n;
+            edges = e;
+            self.delete_duplicate_edges(&mut edges);
+            edges = self.delete_dominated_edges(edges);
+            println!(
+                "{} nodes and {} edges left after simplification",
+                nodes.len(),
+                edges.len()
+            );
+        }
+
+        let restrictions = if raw_restrictions.is_empty() {
+            Vec::new()
+        } else {
+            let node_ids = Self::node_ids(&nodes);
+            let restrictions = resolve_restrictions(&raw_restrictions, &edges, &node_ids);
+            println!("Resolved {} turn restrictions", restrictions.len());
+            restrictions
+        };
+        Ok((nodes, edges, restrictions))
     }
+
+    fn node_ids(nodes: &[Node]) -> HashMap<OsmNodeId, NodeId> {
+        nodes
+            .iter()
+            .enumerate()
+            .map(|(i, n)| (n.osm_id, i))
+            .collect()
+    }
+
     fn internal_metric_count(&self) -> usize {
         self.node_metrics.len() + self.cost_metrics.len() + self.tag_metrics.len()
     }
@@ -164,162 +370,265 @@ impl<'a, Filter: EdgeFilter> Loader<'a, Filter> {
             for id in ids {
                 set.insert(id);
             }
-            send.send(set)
-                .expect("Cannot send node ids back to main thread");
+            // the loader stops listening if it failed in the meantime
+            let _ = send.send(set);
         });
         recv
     }
 
-    fn calculate_cost_metrics(&self, edges: &mut [Edge]) {
+    fn is_inside_area(&self, lat: Latitude, lng: Longitude) -> bool {
+        self.options
+            .area
+            .as_ref()
+            .map(|a| a.contains(lat, lng))
+            .unwrap_or(true)
+    }
+
+    /// Reports edges referencing nodes that are not part of the pbf file and
+    /// handles them according to the `missing_nodes` option. Returns whether
+    /// edges were dropped.
+    fn handle_missing_nodes(
+        &self,
+        found: &HashSet<OsmNodeId>,
+        edges: &mut Vec<Edge>,
+    ) -> Result<bool, ExtractError> {
+        let is_missing = |e: &Edge| !found.contains(&e.source) || !found.contains(&e.dest);
+        let affected: BTreeSet<OsmWayId> = edges
+            .iter()
+            .filter(|e| is_missing(e))
+            .map(|e| e.osm_way)
+            .collect();
+        if affected.is_empty() {
+            return Ok(false);
+        }
+        let samples: Vec<String> = affected.iter().take(10).map(|w| w.to_string()).collect();
+        println!(
+            "{} edges of {} ways reference nodes missing from the extract, e.g. ways {}",
+            edges.iter().filter(|e| is_missing(e)).count(),
+            affected.len(),
+            samples.join(", ")
+        );
+
+        match self.options.missing_nodes {
+            MissingNodes::Abort => {
+                let e = edges
+                    .iter()
+                    .find(|e| is_missing(e))
+                    .expect("an edge references a missing node");
+                let node = if found.contains(&e.source) {
+                    e.dest
+                } else {
+                    e.source
+                };
+                return Err(ExtractError::MissingNode {
+                    way: e.osm_way,
+                    node,
+                });
+            }
+            MissingNodes::DropWay => edges.retain(|e| !affected.contains(&e.osm_way)),
+            MissingNodes::Split => edges.retain(|e| !is_missing(e)),
+        }
+        println!("{} edges left", edges.len());
+        Ok(true)
+    }
+
+    /// Deletes edges with an endpoint outside of the area and nodes that
+    /// are not part of any edge afterwards.
+    fn delete_clipped_edges(&self, nodes: &mut Vec<Node>, edges: &mut Vec<Edge>) {
+        let inside: HashSet<OsmNodeId> = nodes.iter().map(|n| n.osm_id).collect();
+        edges.retain(|e| inside.contains(&e.source) && inside.contains(&e.dest));
+
+        let used: HashSet<OsmNodeId> = edges.iter().flat_map(|e| vec![e.source, e.dest]).collect();
+        nodes.retain(|n| used.contains(&n.osm_id));
+    }
+
+    fn calculate_cost_metrics(&self, edges: &mut [Edge]) -> Result<(), ExtractError> {
         for e in edges {
             for c in &self.cost_metrics {
                 let index = self.metrics_indices[&c.name()];
-                let value = c.calc(&e.costs, &self.metrics_indices).unwrap();
+                let value = c.calc(&e.costs, &self.metrics_indices).map_err(|error| {
+                    ExtractError::Metric {
+                        metric: c.name(),
+                        way: e.osm_way,
+                        error,
+                    }
+                })?;
                 e.costs[index] = value;
             }
         }
+        Ok(())
     }
 
-    fn process_way(&self, w: &Way, id_sender: &Sender<osmpbfreader::NodeId>) -> Vec<Edge> {
+    fn process_way(
+        &self,
+        w: &Way,
+        id_sender: &Sender<osmpbfreader::NodeId>,
+    ) -> Result<Vec<Edge>, ExtractError> {
         let mut edges = Vec::new();
-        if self.edge_filter.is_invalid(&w.tags) {
-            return edges;
+        if w.nodes.len() < 2 || self.edge_filter.is_invalid(&w.tags) {
+            return Ok(edges);
         }
 
-        let tag_costs: Vec<(usize, f64)> = self
-            .tag_metrics
-            .iter()
-            .map(|t| (self.metrics_indices[&t.name()], t.calc(&w.tags).unwrap()))
-            .collect();
-        let is_one_way = self.is_one_way(w);
-        for (index, node) in w.nodes[0..(w.nodes.len() - 1)].iter().enumerate() {
-            id_sender.send(*node).expect("could not send id to id set");
-            let mut edge = Edge::new(
-                node.0 as NodeId,
-                w.nodes[index + 1].0 as NodeId,
-                self.internal_metric_count(),
-            );
-            for (i, t) in &tag_costs {
-                edge.costs[*i] = *t;
-            }
-            edges.push(edge);
-            if !is_one_way {
+        let directions = self.edge_filter.directions(&w.tags);
+        if !directions.forward && !directions.backward {
+            return Ok(edges);
+        }
+        let tag_costs = |direction: Direction| -> Result<Vec<(usize, f64)>, ExtractError> {
+            self.tag_metrics
+                .iter()
+                .map(|t| {
+                    t.calc_directed(&w.tags, direction)
+                        .map(|c| (self.metrics_indices[&t.name()], c))
+                        .map_err(|error| ExtractError::Metric {
+                            metric: t.name(),
+                            way: w.id.0 as OsmWayId,
+                            error,
+                        })
+                })
+                .collect()
+        };
+        let forward_costs = if directions.forward {
+            tag_costs(Direction::Forward)?
+        } else {
+            Vec::new()
+        };
+        let backward_costs = if directions.backward {
+            tag_costs(Direction::Backward)?
+        } else {
+            Vec::new()
+        };
+        let mut add_edge =
+            |source: &osmpbfreader::NodeId, dest: &osmpbfreader::NodeId, costs: &[(usize, f64)]| {
                 let mut edge = Edge::new(
-                    w.nodes[index + 1].0 as NodeId,
-                    node.0 as NodeId,
+                    source.0 as NodeId,
+                    dest.0 as NodeId,
+                    w.id.0 as OsmWayId,
                     self.internal_metric_count(),
                 );
-                for (i, t) in &tag_costs {
+                for (i, t) in costs {
                     edge.costs[*i] = *t;
                 }
                 edges.push(edge);
+            };
+        for pair in w.nodes.windows(2) {
+            id_sender
+                .send(pair[0])
+                .map_err(|_| ExtractError::NodeIdCollection)?;
+            if directions.forward {
+                add_edge(&pair[0], &pair[1], &forward_costs);
+            }
+            if directions.backward {
+                add_edge(&pair[1], &pair[0], &backward_costs);
             }
         }
 
-        id_sender
-            .send(*w.nodes.last().unwrap())
-            .expect("could not send id to id set");
-        edges
+        if let Some(last) = w.nodes.last() {
+            id_sender
+                .send(*last)
+                .map_err(|_| ExtractError::NodeIdCollection)?;
+        }
+        Ok(edges)
     }
-    fn is_one_way(&self, way: &Way) -> bool {
-        let one_way = way.tags.get("oneway");
-        let highway = way.tags.get("highway");
-        let junction = way.tags.get("junction");
-        match one_way.map(smartstring::SmartString::as_ref) {
-            Some("yes") | Some("true") => true,
-            Some("no") | Some("false") => false,
-            _ => {
-                highway.map(|h| h == "motorway").unwrap_or(false)
-                    || junction
-                        .map(|j| j == "roundabout" || j == "circular")
-                        .unwrap_or(false)
+    /// Builds the smoothed elevation profile of every way and splits it into
+    /// the profiles of its edges. Nodes missing from `nodes` split ways.
+    fn way_profiles(
+        &self,
+        nodes: &[Node],
+        ways: &[(OsmWayId, Vec<OsmNodeId>)],
+    ) -> Result<EdgeProfiles, ExtractError> {
+        let mut profiles = EdgeProfiles::new();
+        let (smoothing, elevation) = match (self.options.smoothing, &self.elevation) {
+            (Some(smoothing), Some(elevation)) => (smoothing, elevation),
+            _ => return Ok(profiles),
+        };
+        let by_id: HashMap<OsmNodeId, &Node> = nodes.iter().map(|n| (n.osm_id, n)).collect();
+        for (way, way_nodes) in ways {
+            for part in way_nodes.split(|n| !by_id.contains_key(n)) {
+                if part.len() < 2 {
+                    continue;
+                }
+                let mut profile = vec![by_id[&part[0]].clone()];
+                // position of every way node in the profile
+                let mut positions = vec![0];
+                for pair in part.windows(2) {
+                    let (source, dest) = (by_id[&pair[0]], by_id[&pair[1]]);
+                    match self.options.elevation_spacing {
+                        Some(spacing) => profile.extend(
+                            sample_profile(elevation.as_ref(), source, dest, spacing)?
+                                .into_iter()
+                                .skip(1),
+                        ),
+                        None => profile.push(dest.clone()),
+                    }
+                    positions.push(profile.len() - 1);
+                }
+                smoothing.apply(&mut profile);
+                for (i, pair) in part.windows(2).enumerate() {
+                    let edge_profile = profile[positions[i]..=positions[i + 1]].to_vec();
+                    profiles.insert((*way, pair[0], pair[1]), edge_profile);
+                }
+            }
+        }
+        Ok(profiles)
+    }
+
+    /// The elevation profile of an edge that still refers to OSM node ids.
+    fn edge_profile(
+        &self,
+        e: &Edge,
+        source: &Node,
+        dest: &Node,
+        profiles: &EdgeProfiles,
+    ) -> Result<Vec<Node>, ExtractError> {
+        if let Some(profile) = profiles.get(&(e.osm_way, e.source, e.dest)) {
+            return Ok(profile.clone());
+        }
+        if let Some(profile) = profiles.get(&(e.osm_way, e.dest, e.source)) {
+            return Ok(profile.iter().rev().cloned().collect());
+        }
+        match (&self.elevation, self.options.elevation_spacing) {
+            (Some(elevation), Some(spacing)) => {
+                sample_profile(elevation.as_ref(), source, dest, spacing)
             }
+            _ => Ok(vec![source.clone(), dest.clone()]),
         }
     }
 
-    fn rename_node_ids_and_calculate_node_metrics(&self, nodes: &mut [Node], edges: &mut [Edge]) {
+    fn rename_node_ids_and_calculate_node_metrics(
+        &self,
+        nodes: &mut [Node],
+        edges: &mut [Edge],
+        profiles: &EdgeProfiles,
+    ) -> Result<(), ExtractError> {
         use std::collections::hash_map::HashMap;
 
         let map: HashMap<OsmNodeId, (usize, &Node)> =
             nodes.iter().enumerate().map(|n| (n.1.osm_id, n)).collect();
         for e in edges.iter_mut() {
-            let (source_id, source) = map[&e.source];
-            let (dest_id, dest) = map[&e.dest];
+            let lookup = |node: OsmNodeId| {
+                map.get(&node).copied().ok_or(ExtractError::MissingNode {
+                    way: e.osm_way,
+                    node,
+                })
+            };
+            let (source_id, source) = lookup(e.source)?;
+            let (dest_id, dest) = lookup(e.dest)?;
+            let profile = self.edge_profile(e, source, dest, profiles)?;
             e.source = source_id;
             e.dest = dest_id;
             for n in &self.node_metrics {
                 let index = self.metrics_indices[&n.name()];
-                let value = n.calc(source, dest).unwrap();
+                let value = n
+                    .calc_profile(&profile)
+                    .map_err(|error| ExtractError::Metric {
+                        metric: n.name(),
+                        way: e.osm_way,
+                        error,
+                    })?;
                 e.costs[index] = value;
             }
         }
-    }
-
-    pub fn srtm(&self, lat: Latitude, lng: Longitude) -> f64 {
-        use byteorder::{BigEndian, ReadBytesExt};
-        use std::io::{Seek, SeekFrom};
-
-        let second = 1.0 / 3600.0;
-
-        let north = self.f64_to_whole_number(lat);
-        let east = self.f64_to_whole_number(lng);
-
-        let file_name = if east > 0 {
-            format!("/N{:02}E{:03}.hgt", north, east)
-        } else {
-            format!("/N{:02}W{:03}.hgt", north, east.abs() + 1)
-        };
-
-        let mut srtm_file = String::new();
-        srtm_file.push_str(self.srtm_path);
-        srtm_file.push_str(&file_name);
-        let mut f = match File::open(&srtm_file) {
-            Ok(f) => f,
-            Err(_) => {
-                println!("could not find file: {}", file_name);
-                println!("lat: {}, lng: {}", lat, lng);
-                println!("north: {}, east: {}", north, east);
-                std::process::exit(1);
-            }
-        };
-        let lat_offset = 3601.0 - lat.fract() / second;
-        let lng_offset = lng.abs().fract() / second;
-
-        let lat_offset_floor = lat_offset.floor() as u64;
-        let lat_offset_ceil = lat_offset.ceil() as u64;
-        let long_offset_floor = lng_offset.floor() as u64;
-        let long_offset_ceil = lng_offset.ceil() as u64;
-
-        let mut read_offsets = |lat_offset: u64, long_offset: u64| -> f64 {
-            let seek_val = ((lat_offset - 1) * 3601 + (long_offset)) * 2;
-            f.seek(SeekFrom::Start(seek_val)).unwrap_or_else(|_| {
-                panic!(
-                    "Seeking to value failed. latoff: {}, lngoff: {}, seekval: {}",
-                    lat_offset, lng_offset, seek_val,
-                )
-            });
-
-            f64::from(
-                f.read_i16::<BigEndian>()
-                    .unwrap_or_else(|_| panic!("Reading failed at {}, {}", lat, lng)),
-            )
-        };
-
-        let h1 = read_offsets(lat_offset_floor, long_offset_floor);
-        let h2 = read_offsets(lat_offset_ceil, long_offset_floor);
-        let h3 = read_offsets(lat_offset_floor, long_offset_ceil);
-        let h4 = read_offsets(lat_offset_ceil, long_offset_ceil);
-
-        let h1_weight = (1.0 - lat_offset.fract()) * (1.0 - lng_offset.fract());
-        let h2_weight = lat_offset.fract() * (1.0 - lng_offset.fract());
-        let h3_weight = (1.0 - lat_offset.fract()) * lng_offset.fract();
-        let h4_weight = lat_offset.fract() * lng_offset.fract();
-
-        h1 * h1_weight + h2 * h2_weight + h3 * h3_weight + h4 * h4_weight
-    }
-
-    fn f64_to_whole_number(&self, x: f64) -> i64 {
-        x.trunc() as i64
+        Ok(())
     }
 
     fn delete_duplicate_edges(&self, edges: &mut Vec<Edge>) {
@@ -369,9 +678,11 @@ impl<'a, Filter: EdgeFilter> Loader<'a, Filter> {
 
 pub type NodeId = usize;
 pub type OsmNodeId = usize;
+pub type OsmWayId = usize;
 pub type Latitude = f64;
 pub type Longitude = f64;
 
+#[derive(Debug, Clone, PartialEq)]
 pub struct Node {
     pub osm_id: OsmNodeId,
     pub lat: Latitude,
@@ -393,15 +704,58 @@ impl Node {
 pub struct Edge {
     pub source: NodeId,
     pub dest: NodeId,
+    /// The way this edge was created from, 0 if unknown.
+    pub osm_way: OsmWayId,
+    /// Coordinates of the nodes removed when contracting this edge.
+    pub geometry: Vec<(Latitude, Longitude)>,
     costs: Vec<f64>,
 }
 
 impl Edge {
-    pub fn new(source: NodeId, dest: NodeId, cost_count: usize) -> Edge {
+    pub fn new(source: NodeId, dest: NodeId, osm_way: OsmWayId, cost_count: usize) -> Edge {
         let costs = vec![0.0; cost_count];
         Edge {
             source,
             dest,
+            osm_way,
+            geometry: Vec::new(),
+            costs,
+        }
+    }
+
+    pub fn with_costs(source: NodeId, dest: NodeId, costs: Vec<f64>) -> Edge {
+        Edge {
+            source,
+            dest,
+            osm_way: 0,
+            geometry: Vec::new(),
+            costs,
+        }
+    }
+
+    /// Merges consecutive edges into one edge. `lengths` are used to weight
+    /// the costs of the single edges.
+    pub fn merge(path: &[&Edge], lengths: &[f64], aggregations: &[Aggregation]) -> Edge {
+        let first = path[0];
+        let last = path[path.len() - 1];
+        let costs = aggregations
+            .iter()
+            .enumerate()
+            .map(|(i, a)| {
+                let values: Vec<f64> = path.iter().map(|e| e.costs[i]).collect();
+                a.combine(&values, lengths)
+            })
+            .collect();
+        let osm_way = if path.iter().all(|e| e.osm_way == first.osm_way) {
+            first.osm_way
+        } else {
+            0
+        };
+        Edge {
+            source: first.source,
+            dest: last.dest,
+            osm_way,
+            geometry: Vec::new(),
             costs,
         }
     }
@@ -426,3 +780,34 @@ impl PartialEq for Edge {
             && self.costs.iter().zip(rhs.costs.iter()).all(|(a, b)| a == b)
     }
 }
+
+#[test]
+fn reversible_ways_add_no_nodes() {
+    use super::testing::tags;
+
+    let loader = Loader::new(
+        "",
+        None,
+        CarEdgeFilter,
+        Vec::new(),
+        Vec::new(),
+        Vec::new(),
+        HashSet::new(),
+        Grid::new_ptr(),
+        LoaderOptions::default(),
+    );
+    let way = |oneway| Way {
+        id: osmpbfreader::WayId(1),
+        tags: tags(&[("highway", "residential"), ("oneway", oneway)]),
+        nodes: vec![osmpbfreader::NodeId(1), osmpbfreader::NodeId(2)],
+    };
+    let (id_sender, id_receiver) = channel();
+
+    let edges = loader.process_way(&way("reversible"), &id_sender).unwrap();
+    assert!(edges.is_empty());
+    assert!(id_receiver.try_recv().is_err());
+
+    let edges = loader.process_way(&way("yes"), &id_sender).unwrap();
+    assert_eq!(1, edges.len());
+    assert_eq!(2, id_receiver.try_iter().count());
+}
@@ -0,0 +1,573 @@
+/*
+Pbfextractor creates graph files for the cycle-routing projects from pbf and srtm data
+Copyright (C) 2018  Florian Barth
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use super::access::{access, directions, Access, Direction, Directions};
+#[cfg(test)]
+use super::dimensions::VehicleDimensions;
+use super::maxspeed::Vehicle;
+use super::metrics::*;
+use super::script::{ScriptError, ScriptKind, ScriptedMetric};
+#[cfg(test)]
+use super::testing::tags;
+use super::units::*;
+
+use osmpbfreader::Tags;
+use serde::Deserialize;
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use std::rc::Rc;
+
+#[derive(Debug)]
+pub enum ProfileError {
+    Io(std::io::Error),
+    Toml(toml::de::Error),
+    Json(serde_json::Error),
+    Script(ScriptError),
+    /// A climb penalty parameter out of its range, given as the name of
+    /// the penalty, the parameter, what it has to be and its value.
+    ClimbPenalty(String, &'static str, &'static str, f64),
+}
+
+impl fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ProfileError::Io(e) => write!(f, "could not read profile: {}", e),
+            ProfileError::Toml(e) => write!(f, "invalid profile: {}", e),
+            ProfileError::Json(e) => write!(f, "invalid profile: {}", e),
+            ProfileError::Script(e) => write!(f, "invalid script {}", e),
+            ProfileError::ClimbPenalty(name, parameter, requirement, value) => write!(
+                f,
+                "{} of climb penalty {} has to be {}, found {}",
+                parameter, name, requirement, value
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ProfileError {}
+
+/// An extraction profile as read from a TOML or JSON file. It describes
+/// which ways are part of the graph, the lookup tables of the tag
+/// metrics it defines and which metrics are written to the graph.
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    #[serde(default)]
+    pub filter: FilterRules,
+    #[serde(default)]
+    pub speed: BTreeMap<String, SpeedTable>,
+    #[serde(default)]
+    pub unsuitability: BTreeMap<String, UnsuitabilityTable>,
+    #[serde(default)]
+    pub script: BTreeMap<String, Script>,
+    #[serde(default)]
+    pub climb_penalty: BTreeMap<String, ClimbPenaltyParameters>,
+    #[serde(default)]
+    pub metrics: MetricNames,
+}
+
+impl Profile {
+    /// Reads a profile, files ending in `.json` are parsed as JSON,
+    /// everything else as TOML.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Profile, ProfileError> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path).map_err(ProfileError::Io)?;
+        let profile: Profile = if path.extension().map(|e| e == "json").unwrap_or(false) {
+            serde_json::from_str(&content).map_err(ProfileError::Json)?
+        } else {
+            toml::from_str(&content).map_err(ProfileError::Toml)?
+        };
+        profile.validate()
+    }
+
+    /// Rejects parameters that would give infinite or NaN costs.
+    fn validate(self) -> Result<Profile, ProfileError> {
+        for (name, parameters) in &self.climb_penalty {
+            let gradient = parameters.reference_gradient;
+            if !gradient.is_finite() || gradient <= 0.0 {
+                return Err(ProfileError::ClimbPenalty(
+                    name.clone(),
+                    "reference_gradient",
+                    "positive",
+                    gradient,
+                ));
+            }
+            // below 1 gentle climbs would cost more than steep ones
+            let exponent = parameters.exponent;
+            if !exponent.is_finite() || exponent < 1.0 {
+                return Err(ProfileError::ClimbPenalty(
+                    name.clone(),
+                    "exponent",
+                    "at least 1",
+                    exponent,
+                ));
+            }
+        }
+        Ok(self)
+    }
+
+    /// All tag metrics defined by the lookup tables of this profile.
+    pub fn tag_metrics(&self) -> Vec<Rc<dyn TagMetric<f64>>> {
+        let mut metrics: Vec<Rc<dyn TagMetric<f64>>> = Vec::new();
+        for (name, table) in &self.speed {
+            metrics.push(Rc::new(TableSpeed {
+                name: name.clone(),
+                table: table.clone(),
+            }));
+        }
+        for (name, table) in &self.unsuitability {
+            metrics.push(Rc::new(TableUnsuitability {
+                name: name.clone(),
+                table: table.clone(),
+            }));
+        }
+        metrics
+    }
+
+    /// All node metrics defined by this profile.
+    pub fn node_metrics(&self) -> Vec<Rc<dyn NodeMetric<f64>>> {
+        let mut metrics: Vec<Rc<dyn NodeMetric<f64>>> = Vec::new();
+        for (name, parameters) in &self.climb_penalty {
+            metrics.push(Rc::new(ClimbPenalty {
+                name: name.clone(),
+                reference_gradient: parameters.reference_gradient,
+                exponent: parameters.exponent,
+            }));
+        }
+        metrics
+    }
+
+    /// All scripted metrics defined by this profile.
+    pub fn scripts(&self) -> Result<Vec<ScriptedMetric>, ProfileError> {
+        let mut scripts = Vec::new();
+        for (name, script) in &self.script {
+            let kind: ScriptKind = script.kind.parse().map_err(ProfileError::Script)?;
+            let mut metric = ScriptedMetric::new(name, kind, &script.expression)
+                .map_err(ProfileError::Script)?;
+            if let Some(combine) = script.combine {
+                metric.set_aggregation(combine);
+            }
+            scripts.push(metric);
+        }
+        Ok(scripts)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Script {
+    pub kind: String,
+    pub expression: String,
+    /// How the metric is combined when edges are merged.
+    pub combine: Option<Aggregation>,
+}
+
+/// Parameters of a `ClimbPenalty`, missing ones take the defaults of the
+/// built-in metric.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ClimbPenaltyParameters {
+    #[serde(default = "default_reference_gradient")]
+    pub reference_gradient: f64,
+    #[serde(default = "default_exponent")]
+    pub exponent: f64,
+}
+
+fn default_reference_gradient() -> f64 {
+    ClimbPenalty::default().reference_gradient
+}
+
+fn default_exponent() -> f64 {
+    ClimbPenalty::default().exponent
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MetricNames {
+    #[serde(default)]
+    pub export: Vec<String>,
+    #[serde(default)]
+    pub internal: Vec<String>,
+}
+
+/// Matches a tag. Without `values` every value matches, values listed in
+/// `except` never match.
+#[derive(Debug, Clone, Deserialize)]
+pub struct TagCondition {
+    pub tag: String,
+    #[serde(default)]
+    pub values: Vec<String>,
+    #[serde(default)]
+    pub except: Vec<String>,
+}
+
+impl TagCondition {
+    pub fn matches(&self, tags: &Tags) -> bool {
+        match tags.get(self.tag.as_str()) {
+            Some(v) => {
+                let v: &str = v.as_ref();
+                (self.values.is_empty() || self.values.iter().any(|a| a == v))
+                    && !self.except.iter().any(|e| e == v)
+            }
+            None => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct AccessRule {
+    #[serde(flatten)]
+    pub condition: TagCondition,
+    pub allow: bool,
+}
+
+/// Edge filter rules. Ways forbidden by the hierarchy of `access_keys` are
+/// never used. Otherwise the first matching access rule decides whether a
+/// way is used, or else its `highway` value is checked against the allowed
+/// and forbidden lists. An empty allowed list allows every highway that is
+/// not forbidden. Ways without a highway tag are never used.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FilterRules {
+    #[serde(default)]
+    pub allowed_highways: Vec<String>,
+    #[serde(default)]
+    pub forbidden_highways: Vec<String>,
+    #[serde(default)]
+    pub access: Vec<AccessRule>,
+    /// Access keys from the most general to the most specific one, e.g.
+    /// `["access", "vehicle", "bicycle"]`.
+    #[serde(default)]
+    pub access_keys: Vec<String>,
+    /// The mode whose `oneway:<mode>` tag overrides `oneway`, e.g.
+    /// `"bicycle"`.
+    #[serde(default)]
+    pub oneway_mode: Option<String>,
+    /// Whether contraflow cycle lanes like `cycleway=opposite_lane` open
+    /// one-way streets in both directions.
+    #[serde(default)]
+    pub contraflow: bool,
+}
+
+impl EdgeFilter for FilterRules {
+    fn is_invalid(&self, tags: &Tags) -> bool {
+        if access(tags, &self.access_keys) == Some(Access::Forbidden) {
+            return true;
+        }
+        if let Some(rule) = self.access.iter().find(|r| r.condition.matches(tags)) {
+            return !rule.allow;
+        }
+        match tags.get("highway").map(smartstring::alias::String::as_str) {
+            Some(h) => {
+                self.forbidden_highways.iter().any(|f| f == h)
+                    || !self.allowed_highways.is_empty()
+                        && !self.allowed_highways.iter().any(|a| a == h)
+            }
+            None => true,
+        }
+    }
+
+    fn directions(&self, tags: &Tags) -> Directions {
+        let mode = self.oneway_mode.as_deref();
+        let mode_specific = mode.and_then(|m| tags.get(format!("oneway:{}", m).as_str()));
+        if self.contraflow && has_contraflow_lane(tags) && mode_specific.is_none() {
+            return Directions::BOTH;
+        }
+        directions(tags, mode)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SpeedTable {
+    pub max: f64,
+    pub default: f64,
+    #[serde(default)]
+    pub highway: BTreeMap<String, f64>,
+}
+
+/// A speed metric defined by a profile. The `maxspeed` tag is honoured up
+/// to the configured maximum.
+pub struct TableSpeed {
+    name: String,
+    table: SpeedTable,
+}
+
+impl Metric for TableSpeed {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn aggregation(&self) -> Aggregation {
+        Aggregation::WeightedMean
+    }
+}
+
+impl TableSpeed {
+    fn speed(&self, tags: &Tags, direction: Option<Direction>) -> MetricResult<KilometersPerHour> {
+        let tag_speed = tags
+            .get("highway")
+            .and_then(|h| self.table.highway.get(h.as_str()))
+            .copied()
+            .unwrap_or(self.table.default);
+        limit_speed(tags, tag_speed, self.table.max, Vehicle::Car, direction)
+    }
+}
+
+impl TagMetric<KilometersPerHour> for TableSpeed {
+    fn calc(&self, tags: &Tags) -> MetricResult<KilometersPerHour> {
+        self.speed(tags, None)
+    }
+
+    fn calc_directed(&self, tags: &Tags, direction: Direction) -> MetricResult<KilometersPerHour> {
+        self.speed(tags, Some(direction))
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct TagValue {
+    #[serde(flatten)]
+    pub condition: TagCondition,
+    pub value: f64,
+    /// Only applies when travelling in this direction, e.g. for
+    /// `cycleway:right` in `forward` direction.
+    #[serde(default)]
+    pub direction: Option<Direction>,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UnsuitabilityTable {
+    pub default: f64,
+    #[serde(default)]
+    pub highway: BTreeMap<String, f64>,
+    #[serde(default)]
+    pub overrides: Vec<TagValue>,
+}
+
+/// An unsuitability metric defined by a profile. The first matching
+/// override wins over the highway table, overrides limited to a direction
+/// are only used for that direction.
+pub struct TableUnsuitability {
+    name: String,
+    table: UnsuitabilityTable,
+}
+
+impl Metric for TableUnsuitability {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn aggregation(&self) -> Aggregation {
+        Aggregation::WeightedMean
+    }
+}
+
+impl TableUnsuitability {
+    fn value(&self, tags: &Tags, direction: Option<Direction>) -> f64 {
+        if let Some(o) = self
+            .table
+            .overrides
+            .iter()
+            .filter(|o| o.direction.is_none() || o.direction == direction)
+            .find(|o| o.condition.matches(tags))
+        {
+            return o.value;
+        }
+        tags.get("highway")
+            .and_then(|h| self.table.highway.get(h.as_str()))
+            .copied()
+            .unwrap_or(self.table.default)
+    }
+}
+
+impl TagMetric<f64> for TableUnsuitability {
+    fn calc(&self, tags: &Tags) -> MetricResult<f64> {
+        Ok(self.value(tags, None))
+    }
+
+    fn calc_directed(&self, tags: &Tags, direction: Direction) -> MetricResult<f64> {
+        Ok(self.value(tags, Some(direction)))
+    }
+}
+
+#[test]
+fn bicycle_profile_matches_builtin() {
+    let profile = Profile::from_file("profiles/bicycle.toml").unwrap();
+    let ways = [
+        tags(&[("highway", "primary")]),
+        tags(&[("highway", "motorway")]),
+        tags(&[("highway", "service")]),
+        tags(&[("highway", "residential"), ("bicycle", "no")]),
+        tags(&[("highway", "residential"), ("access", "private")]),
+        tags(&[
+            ("highway", "motorway"),
+            ("access", "no"),
+            ("bicycle", "yes"),
+        ]),
+        tags(&[("highway", "trunk"), ("cycleway", "lane")]),
+        tags(&[
+            ("highway", "trunk"),
+            ("cycleway", "lane"),
+            ("vehicle", "no"),
+        ]),
+        tags(&[("highway", "trunk"), ("sidewalk", "both")]),
+        tags(&[("highway", "trunk"), ("sidewalk", "no")]),
+        tags(&[("building", "yes")]),
+        tags(&[("highway", "residential"), ("oneway", "yes")]),
+        tags(&[
+            ("highway", "residential"),
+            ("oneway", "yes"),
+            ("oneway:bicycle", "no"),
+        ]),
+        tags(&[
+            ("highway", "residential"),
+            ("oneway", "-1"),
+            ("cycleway", "opposite_lane"),
+        ]),
+        tags(&[
+            ("highway", "residential"),
+            ("oneway", "yes"),
+            ("cycleway:left", "opposite_track"),
+            ("oneway:bicycle", "yes"),
+        ]),
+        tags(&[("junction", "roundabout"), ("highway", "tertiary")]),
+        tags(&[("highway", "primary"), ("cycleway:right", "lane")]),
+        tags(&[("highway", "primary"), ("cycleway:left", "lane")]),
+        tags(&[("highway", "secondary"), ("cycleway:both", "track")]),
+        tags(&[("highway", "secondary"), ("cycleway:right", "separate")]),
+        tags(&[
+            ("highway", "tertiary"),
+            ("cycleway:left", "no"),
+            ("sidewalk", "yes"),
+        ]),
+    ];
+    let unsuitability = &profile.tag_metrics()[0];
+    for way in &ways {
+        assert_eq!(
+            BicycleEdgeFilter.is_invalid(way),
+            profile.filter.is_invalid(way)
+        );
+        assert_eq!(
+            BicycleEdgeFilter.directions(way),
+            profile.filter.directions(way)
+        );
+        assert_eq!(
+            TagMetric::<f64>::calc(&BicycleUnsuitability, way).unwrap(),
+            unsuitability.calc(way).unwrap()
+        );
+        for direction in [Direction::Forward, Direction::Backward] {
+            assert_eq!(
+                BicycleUnsuitability.calc_directed(way, direction).unwrap(),
+                unsuitability.calc_directed(way, direction).unwrap()
+            );
+        }
+    }
+}
+
+#[test]
+fn car_profile_matches_builtin() {
+    let profile = Profile::from_file("profiles/car.toml").unwrap();
+    let ways = [
+        tags(&[("highway", "motorway")]),
+        tags(&[("highway", "primary"), ("maxspeed", "70")]),
+        tags(&[("highway", "living_street"), ("maxspeed", "DE:walk")]),
+        tags(&[("highway", "residential")]),
+        tags(&[("highway", "residential"), ("motor_vehicle", "destination")]),
+        tags(&[("highway", "residential"), ("motorcar", "no")]),
+        tags(&[
+            ("highway", "primary"),
+            ("access", "no"),
+            ("motor_vehicle", "yes"),
+        ]),
+        tags(&[("highway", "footway")]),
+        tags(&[("railway", "rail")]),
+        tags(&[("highway", "residential"), ("oneway", "yes")]),
+        tags(&[
+            ("highway", "residential"),
+            ("oneway", "yes"),
+            ("oneway:bicycle", "no"),
+            ("cycleway", "opposite"),
+        ]),
+        tags(&[
+            ("highway", "residential"),
+            ("oneway", "yes"),
+            ("oneway:motor_vehicle", "no"),
+        ]),
+    ];
+    let speed = &profile.tag_metrics()[0];
+    for way in &ways {
+        assert_eq!(
+            CarEdgeFilter.is_invalid(way),
+            profile.filter.is_invalid(way)
+        );
+        assert_eq!(
+            CarEdgeFilter.directions(way),
+            profile.filter.directions(way)
+        );
+        assert_eq!(
+            TagMetric::<f64>::calc(&CarSpeed, way).unwrap(),
+            speed.calc(way).unwrap()
+        );
+    }
+}
+
+#[test]
+fn truck_dimensions_apply_to_profile_filters() {
+    let profile = Profile::from_file("profiles/car.toml").unwrap();
+    let filter = DimensionFilter {
+        filter: profile.filter,
+        dimensions: VehicleDimensions {
+            weight: Some(Tonnes(12.0)),
+            height: Some(Meters(4.0)),
+            ..VehicleDimensions::default()
+        },
+    };
+    assert!(!filter.is_invalid(&tags(&[("highway", "primary")])));
+    assert!(filter.is_invalid(&tags(&[("highway", "primary"), ("maxweight", "7.5")])));
+    assert!(filter.is_invalid(&tags(&[("highway", "primary"), ("maxheight", "3.8 m")])));
+    assert!(filter.is_invalid(&tags(&[("highway", "footway")])));
+}
+
+#[test]
+fn climb_penalty_parameters() {
+    let profile: Profile =
+        toml::from_str("[climb_penalty.SteepClimbs]\nexponent = 3.0\n\n[climb_penalty.Climbs]\n")
+            .unwrap();
+    let names: Vec<String> = profile.node_metrics().iter().map(|m| m.name()).collect();
+    assert_eq!(vec!["Climbs", "SteepClimbs"], names);
+    assert_eq!(5.0, profile.climb_penalty["SteepClimbs"].reference_gradient);
+    assert_eq!(3.0, profile.climb_penalty["SteepClimbs"].exponent);
+    assert_eq!(2.0, profile.climb_penalty["Climbs"].exponent);
+    assert!(profile.validate().is_ok());
+
+    let linear: Profile = toml::from_str("[climb_penalty.Climbs]\nexponent = 1.0\n").unwrap();
+    assert!(linear.validate().is_ok());
+
+    for invalid in [
+        "reference_gradient = 0.0",
+        "reference_gradient = -5.0",
+        "exponent = nan",
+        "exponent = 0.5",
+    ] {
+        let profile: Profile =
+            toml::from_str(&format!("[climb_penalty.Climbs]\n{}\n", invalid)).unwrap();
+        assert!(profile.validate().is_err());
+    }
+}
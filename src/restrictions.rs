@@ -0,0 +1,480 @@
+/*
+Pbfextractor creates graph files for the cycle-routing projects from pbf and srtm data
+Copyright (C) 2018  Florian Barth
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Turn restrictions from `type=restriction` relations.
+//!
+//! Relations are parsed into `RawRestriction`s while reading the pbf file.
+//! With the access keys of a transport mode, `restriction:<mode>` tags win
+//! over `restriction`, which only applies to vehicles, and modes listed in
+//! `except` are exempt; without modes every `restriction` is kept. Once
+//! the edges are known, each restriction is resolved to the edges leading
+//! from the `from` way over its via node or ways to the `to` way.
+//! Restrictions referring to parts not in the graph are dropped.
+
+#[cfg(test)]
+use super::access;
+use super::pbf::{Edge, NodeId, OsmNodeId, OsmWayId};
+#[cfg(test)]
+use super::testing::tags;
+
+use osmpbfreader::{OsmId, Relation};
+#[cfg(test)]
+use osmpbfreader::{Ref, RelationId, WayId};
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::io::{Result, Write};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RestrictionKind {
+    /// The edge sequence must not be used.
+    No,
+    /// After the first edge, the vehicle has to continue with the
+    /// remaining edges of the sequence.
+    Only,
+}
+
+impl fmt::Display for RestrictionKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RestrictionKind::No => write!(f, "no"),
+            RestrictionKind::Only => write!(f, "only"),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Via {
+    Node(OsmNodeId),
+    Ways(Vec<OsmWayId>),
+}
+
+/// A turn restriction as found in the pbf file, still referring to OSM ids.
+#[derive(Debug, PartialEq)]
+pub struct RawRestriction {
+    kind: RestrictionKind,
+    from: OsmWayId,
+    via: Via,
+    to: OsmWayId,
+}
+
+impl RawRestriction {
+    /// Parses a `type=restriction` relation. Relations with unknown
+    /// restriction values or an unexpected set of members are ignored.
+    ///
+    /// `modes` are the access keys of the transport mode, e.g. `["access",
+    /// "vehicle", "bicycle"]`. Then `restriction:<mode>` wins over
+    /// `restriction`, which only applies to vehicles, and restrictions with
+    /// the mode in `except` are ignored. Without modes every `restriction`
+    /// is used.
+    pub fn from_relation<S: AsRef<str>>(
+        relation: &Relation,
+        modes: Option<&[S]>,
+    ) -> Option<RawRestriction> {
+        let tags = &relation.tags;
+        if tags.get("type").map(|t| t.as_str()) != Some("restriction") {
+            return None;
+        }
+        let restriction = match modes {
+            None => tags.get("restriction")?,
+            Some(modes) => {
+                let is_mode = |value: &str| modes.iter().any(|m| m.as_ref() == value.trim());
+                let excepted = tags
+                    .get("except")
+                    .map(|e| e.split(';').any(is_mode))
+                    .unwrap_or(false);
+                if excepted {
+                    return None;
+                }
+                let specific = modes
+                    .iter()
+                    .rev()
+                    .find_map(|m| tags.get(format!("restriction:{}", m.as_ref()).as_str()));
+                match specific {
+                    Some(r) => r,
+                    None if is_mode("vehicle") => tags.get("restriction")?,
+                    None => return None,
+                }
+            }
+        };
+        let kind = if restriction.starts_with("no_") {
+            RestrictionKind::No
+        } else if restriction.starts_with("only_") {
+            RestrictionKind::Only
+        } else {
+            return None;
+        };
+
+        let mut from = None;
+        let mut to = None;
+        let mut via_node = None;
+        let mut via_ways = Vec::new();
+        for r in &relation.refs {
+            match (r.role.as_str(), r.member) {
+                ("from", OsmId::Way(w)) if from.is_none() => from = Some(w.0 as OsmWayId),
+                ("to", OsmId::Way(w)) if to.is_none() => to = Some(w.0 as OsmWayId),
+                ("via", OsmId::Node(n)) if via_node.is_none() => via_node = Some(n.0 as OsmNodeId),
+                ("via", OsmId::Way(w)) => via_ways.push(w.0 as OsmWayId),
+                ("from", _) | ("to", _) | ("via", _) => return None,
+                _ => {}
+            }
+        }
+        let via = match (via_node, via_ways.is_empty()) {
+            (Some(n), true) => Via::Node(n),
+            (None, false) => Via::Ways(via_ways),
+            _ => return None,
+        };
+        Some(RawRestriction {
+            kind,
+            from: from?,
+            via,
+            to: to?,
+        })
+    }
+
+    pub fn via_node(&self) -> Option<OsmNodeId> {
+        match self.via {
+            Via::Node(n) => Some(n),
+            Via::Ways(_) => None,
+        }
+    }
+
+    fn ways(&self) -> Vec<OsmWayId> {
+        let mut ways = vec![self.from, self.to];
+        if let Via::Ways(via) = &self.via {
+            ways.extend(via);
+        }
+        ways
+    }
+}
+
+/// A turn restriction referring to indices into the final edge list.
+#[derive(Debug, PartialEq)]
+pub struct TurnRestriction {
+    pub kind: RestrictionKind,
+    pub edges: Vec<usize>,
+}
+
+/// Resolves restrictions to edge indices. `node_ids` maps the OSM ids of
+/// via nodes to graph node ids. Restrictions that reference ways or nodes
+/// not part of the graph are dropped.
+pub fn resolve_restrictions(
+    raw: &[RawRestriction],
+    edges: &[Edge],
+    node_ids: &HashMap<OsmNodeId, NodeId>,
+) -> Vec<TurnRestriction> {
+    let wanted: HashSet<OsmWayId> = raw.iter().flat_map(RawRestriction::ways).collect();
+    let mut way_edges: HashMap<OsmWayId, Vec<usize>> = HashMap::new();
+    for (i, e) in edges.iter().enumerate() {
+        if wanted.contains(&e.osm_way) {
+            way_edges.entry(e.osm_way).or_default().push(i);
+        }
+    }
+
+    let mut restrictions = Vec::new();
+    for r in raw {
+        let of_way = |way: OsmWayId| way_edges.get(&way).map(Vec::as_slice).unwrap_or(&[]);
+        // the nodes of a way in the order of its edges
+        let nodes_of = |way: OsmWayId| -> Vec<NodeId> {
+            let mut nodes = Vec::new();
+            for i in of_way(way) {
+                for n in [edges[*i].source, edges[*i].dest] {
+                    if !nodes.contains(&n) {
+                        nodes.push(n);
+                    }
+                }
+            }
+            nodes
+        };
+        let is_end = |way: OsmWayId, node: NodeId| -> bool {
+            let neighbours: HashSet<NodeId> = of_way(way)
+                .iter()
+                .map(|i| &edges[*i])
+                .filter_map(|e| match (e.source == node, e.dest == node) {
+                    (true, false) => Some(e.dest),
+                    (false, true) => Some(e.source),
+                    _ => None,
+                })
+                .collect();
+            neighbours.len() <= 1
+        };
+        // Ways sharing several nodes, e.g. loops, preferably meet at their
+        // ends, otherwise the first shared node along `a` is used.
+        let common = |a: OsmWayId, b: OsmWayId| -> Option<NodeId> {
+            let b_nodes = nodes_of(b);
+            nodes_of(a)
+                .into_iter()
+                .enumerate()
+                .filter(|(_, n)| b_nodes.contains(n))
+                .min_by_key(|(i, n)| (!is_end(a, *n) as u8 + !is_end(b, *n) as u8, *i))
+                .map(|(_, n)| n)
+        };
+
+        // junctions[i] is the node where the i-th way of the chain
+        // from, via..., to is left.
+        let (via_ways, junctions) = match &r.via {
+            Via::Node(n) => match node_ids.get(n) {
+                Some(n) => (Vec::new(), vec![*n]),
+                None => continue,
+            },
+            Via::Ways(via) => {
+                let chain: Vec<OsmWayId> = std::iter::once(r.from)
+                    .chain(via.iter().copied())
+                    .chain(std::iter::once(r.to))
+                    .collect();
+                let junctions: Option<Vec<NodeId>> =
+                    chain.windows(2).map(|w| common(w[0], w[1])).collect();
+                match junctions {
+                    Some(j) => (via.clone(), j),
+                    None => continue,
+                }
+            }
+        };
+
+        let mut path = Vec::new();
+        let mut complete = true;
+        for (i, way) in via_ways.iter().enumerate() {
+            match path_along_way(of_way(*way), edges, junctions[i], junctions[i + 1]) {
+                Some(p) => path.extend(p),
+                None => complete = false,
+            }
+        }
+        if !complete {
+            continue;
+        }
+
+        let first = junctions[0];
+        let last = *junctions.last().expect("restrictions have a via member");
+        for from in of_way(r.from).iter().filter(|i| edges[**i].dest == first) {
+            // a u-turn restriction only affects the way back, not going on
+            let u_turn = r.from == r.to && via_ways.is_empty();
+            let is_to = |i: &&usize| {
+                edges[**i].source == last && (!u_turn || edges[**i].dest == edges[*from].source)
+            };
+            for to in of_way(r.to).iter().filter(is_to) {
+                let mut sequence = vec![*from];
+                sequence.extend(&path);
+                sequence.push(*to);
+                restrictions.push(TurnRestriction {
+                    kind: r.kind,
+                    edges: sequence,
+                });
+            }
+        }
+    }
+    restrictions
+}
+
+/// Finds the edges of one way leading from `start` to `end`.
+fn path_along_way(
+    way_edges: &[usize],
+    edges: &[Edge],
+    start: NodeId,
+    end: NodeId,
+) -> Option<Vec<usize>> {
+    let mut previous: HashMap<NodeId, usize> = HashMap::new();
+    let mut queue = VecDeque::new();
+    queue.push_back(start);
+    while let Some(n) = queue.pop_front() {
+        if n == end {
+            let mut path = Vec::new();
+            let mut current = end;
+            while current != start {
+                let e = previous[&current];
+                path.push(e);
+                current = edges[e].source;
+            }
+            path.reverse();
+            return Some(path);
+        }
+        for e in way_edges.iter().filter(|e| edges[**e].source == n) {
+            let dest = edges[*e].dest;
+            if dest != start && !previous.contains_key(&dest) {
+                previous.insert(dest, *e);
+                queue.push_back(dest);
+            }
+        }
+    }
+    None
+}
+
+/// Writes one restriction per line: kind, number of edges and the edge
+/// indices.
+pub fn write_restrictions<W: Write>(mut out: W, restrictions: &[TurnRestriction]) -> Result<()> {
+    writeln!(&mut out, "# Turn restrictions, build by: pbfextractor")?;
+    writeln!(&mut out, "{}", restrictions.len())?;
+    for r in restrictions {
+        write!(&mut out, "{} {}", r.kind, r.edges.len())?;
+        for e in &r.edges {
+            write!(&mut out, " {}", e)?;
+        }
+        writeln!(&mut out)?;
+    }
+    out.flush()
+}
+
+#[cfg(test)]
+fn edge(source: NodeId, dest: NodeId, way: OsmWayId) -> Edge {
+    let mut e = Edge::with_costs(source, dest, vec![]);
+    e.osm_way = way;
+    e
+}
+
+#[test]
+fn resolve_via_node() {
+    // way 1: 0 <-> 1, way 2: 1 <-> 2, way 3: 1 <-> 3
+    let edges = vec![
+        edge(0, 1, 1),
+        edge(1, 0, 1),
+        edge(1, 2, 2),
+        edge(2, 1, 2),
+        edge(1, 3, 3),
+        edge(3, 1, 3),
+    ];
+    let node_ids: HashMap<OsmNodeId, NodeId> = vec![(101, 1)].into_iter().collect();
+    let raw = vec![RawRestriction {
+        kind: RestrictionKind::No,
+        from: 1,
+        via: Via::Node(101),
+        to: 3,
+    }];
+
+    let resolved = resolve_restrictions(&raw, &edges, &node_ids);
+    assert_eq!(
+        vec![TurnRestriction {
+            kind: RestrictionKind::No,
+            edges: vec![0, 4]
+        }],
+        resolved
+    );
+}
+
+#[test]
+fn resolve_via_way() {
+    // from way 1: 0 -> 1, via way 2: 1 -> 2 -> 3, to way 3: 3 -> 4
+    let edges = vec![
+        edge(0, 1, 1),
+        edge(1, 2, 2),
+        edge(2, 3, 2),
+        edge(3, 2, 2),
+        edge(2, 1, 2),
+        edge(3, 4, 3),
+    ];
+    let raw = vec![RawRestriction {
+        kind: RestrictionKind::Only,
+        from: 1,
+        via: Via::Ways(vec![2]),
+        to: 3,
+    }];
+
+    let resolved = resolve_restrictions(&raw, &edges, &HashMap::new());
+    assert_eq!(1, resolved.len());
+    assert_eq!(vec![0, 1, 2, 5], resolved[0].edges);
+}
+
+#[test]
+fn junction_of_ways_sharing_several_nodes() {
+    // from way 1: 0 -> 1 -> 2, via way 2: 4 -> 1 -> 2 -> 3, to way 3: 3 -> 5
+    let edges = vec![
+        edge(0, 1, 1),
+        edge(1, 2, 1),
+        edge(4, 1, 2),
+        edge(1, 2, 2),
+        edge(2, 3, 2),
+        edge(3, 5, 3),
+    ];
+    let raw = vec![RawRestriction {
+        kind: RestrictionKind::No,
+        from: 1,
+        via: Via::Ways(vec![2]),
+        to: 3,
+    }];
+
+    for _ in 0..10 {
+        let resolved = resolve_restrictions(&raw, &edges, &HashMap::new());
+        assert_eq!(1, resolved.len());
+        assert_eq!(vec![1, 4, 5], resolved[0].edges);
+    }
+}
+
+#[test]
+fn restrictions_for_transport_modes() {
+    let relation = |pairs: &[(&str, &str)]| Relation {
+        id: RelationId(1),
+        tags: tags(pairs),
+        refs: vec![
+            Ref {
+                member: OsmId::Way(WayId(1)),
+                role: "from".into(),
+            },
+            Ref {
+                member: OsmId::Node(osmpbfreader::NodeId(101)),
+                role: "via".into(),
+            },
+            Ref {
+                member: OsmId::Way(WayId(2)),
+                role: "to".into(),
+            },
+        ],
+    };
+    let kind = |pairs: &[(&str, &str)], modes: Option<&[&str]>| {
+        RawRestriction::from_relation(&relation(pairs), modes).map(|r| r.kind)
+    };
+
+    let left_turn = [("type", "restriction"), ("restriction", "no_left_turn")];
+    assert_eq!(
+        Some(RawRestriction {
+            kind: RestrictionKind::No,
+            from: 1,
+            via: Via::Node(101),
+            to: 2,
+        }),
+        RawRestriction::from_relation(&relation(&left_turn), Some(access::CAR))
+    );
+    assert_eq!(
+        Some(RestrictionKind::No),
+        kind(&left_turn, Some(access::BICYCLE))
+    );
+    assert_eq!(None, kind(&left_turn, Some(access::FOOT)));
+    assert_eq!(Some(RestrictionKind::No), kind(&left_turn, None));
+
+    let except = [
+        ("type", "restriction"),
+        ("restriction", "no_left_turn"),
+        ("except", "psv;bicycle"),
+    ];
+    assert_eq!(Some(RestrictionKind::No), kind(&except, Some(access::CAR)));
+    assert_eq!(None, kind(&except, Some(access::BICYCLE)));
+
+    let hgv_only = [
+        ("type", "restriction"),
+        ("restriction:hgv", "only_straight_on"),
+    ];
+    assert_eq!(None, kind(&hgv_only, Some(access::CAR)));
+    assert_eq!(
+        Some(RestrictionKind::Only),
+        kind(&hgv_only, Some(access::HGV))
+    );
+
+    let bicycle_lifted = [
+        ("type", "restriction"),
+        ("restriction", "no_right_turn"),
+        ("restriction:bicycle", "give_way"),
+    ];
+    assert_eq!(None, kind(&bicycle_lifted, Some(access::BICYCLE)));
+    assert_eq!(None, kind(&[("type", "multipolygon")], None));
+}
@@ -0,0 +1,871 @@
+/*
+Pbfextractor creates graph files for the cycle-routing projects from pbf and srtm data
+Copyright (C) 2018  Florian Barth
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+
+//! A small expression language for metrics that are not worth a Rust type.
+//!
+//! ```text
+//! expr    := "if" expr "then" expr "else" expr | or
+//! or      := and ("||" and)*
+//! and     := cmp ("&&" cmp)*
+//! cmp     := sum (("==" | "!=" | "<" | "<=" | ">" | ">=") sum)?
+//! sum     := product (("+" | "-") product)*
+//! product := unary (("*" | "/") unary)*
+//! unary   := ("-" | "!") unary | primary
+//! primary := number | string | "true" | "false" | "(" expr ")"
+//!          | ("source" | "target") "." ("lat" | "long" | "height")
+//!          | name "(" (expr ("," expr)*)? ")"
+//! ```
+//!
+//! Available functions are `tag("key")` (the value of a tag or `""`),
+//! `has("key")`, `cost("Metric")`, `num(string, default)`, `min`, `max`
+//! and `abs`. Which of `tag`/`has`, `source`/`target` and `cost` may be
+//! used depends on whether the script is a tag, node or cost metric.
+
+use super::metrics::*;
+use super::pbf::{MetricIndices, Node};
+
+use osmpbfreader::Tags;
+
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug)]
+pub struct ScriptError(String);
+
+impl fmt::Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+type ScriptResult<T> = Result<T, ScriptError>;
+
+/// The kind of metric a script implements. It decides which inputs the
+/// script can read.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ScriptKind {
+    Tag,
+    Node,
+    Cost,
+}
+
+impl FromStr for ScriptKind {
+    type Err = ScriptError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "tag" => Ok(ScriptKind::Tag),
+            "node" => Ok(ScriptKind::Node),
+            "cost" => Ok(ScriptKind::Cost),
+            _ => Err(ScriptError(format!(
+                "unknown script kind '{}', expected tag, node or cost",
+                s
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Type {
+    Num,
+    Str,
+    Bool,
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Type::Num => write!(f, "number"),
+            Type::Str => write!(f, "string"),
+            Type::Bool => write!(f, "boolean"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Num(f64),
+    Str(String),
+    Bool(bool),
+}
+
+impl Value {
+    fn num(self) -> f64 {
+        match self {
+            Value::Num(n) => n,
+            _ => unreachable!("types are checked when the script is parsed"),
+        }
+    }
+    fn bool(self) -> bool {
+        match self {
+            Value::Bool(b) => b,
+            _ => unreachable!("types are checked when the script is parsed"),
+        }
+    }
+    fn str(self) -> String {
+        match self {
+            Value::Str(s) => s,
+            _ => unreachable!("types are checked when the script is parsed"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Endpoint {
+    Source,
+    Target,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Field {
+    Lat,
+    Long,
+    Height,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Func {
+    Num,
+    Min,
+    Max,
+    Abs,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Lit(Value),
+    Tag(String),
+    Has(String),
+    Cost(String),
+    Node(Endpoint, Field),
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(BinOp, Box<Expr>, Box<Expr>),
+    If(Box<Expr>, Box<Expr>, Box<Expr>),
+    Call(Func, Vec<Expr>),
+}
+
+enum Input<'a> {
+    Tags(&'a Tags),
+    Nodes(&'a Node, &'a Node),
+    Costs(&'a [f64], &'a MetricIndices),
+}
+
+impl Expr {
+    fn check(&self, kind: ScriptKind) -> ScriptResult<Type> {
+        let expect = |e: &Expr, t: Type, what: &str| -> ScriptResult<()> {
+            let actual = e.check(kind)?;
+            if actual == t {
+                Ok(())
+            } else {
+                Err(ScriptError(format!(
+                    "{} must be a {}, found {}",
+                    what, t, actual
+                )))
+            }
+        };
+        let only_in = |k: ScriptKind, what: &str| -> ScriptResult<()> {
+            if k == kind {
+                Ok(())
+            } else {
+                Err(ScriptError(format!(
+                    "{} can not be used in a {:?} script",
+                    what, kind
+                )))
+            }
+        };
+
+        match self {
+            Expr::Lit(Value::Num(_)) => Ok(Type::Num),
+            Expr::Lit(Value::Str(_)) => Ok(Type::Str),
+            Expr::Lit(Value::Bool(_)) => Ok(Type::Bool),
+            Expr::Tag(_) => only_in(ScriptKind::Tag, "tag()").map(|_| Type::Str),
+            Expr::Has(_) => only_in(ScriptKind::Tag, "has()").map(|_| Type::Bool),
+            Expr::Node(..) => only_in(ScriptKind::Node, "source and target").map(|_| Type::Num),
+            Expr::Cost(_) => only_in(ScriptKind::Cost, "cost()").map(|_| Type::Num),
+            Expr::Neg(e) => expect(e, Type::Num, "operand of '-'").map(|_| Type::Num),
+            Expr::Not(e) => expect(e, Type::Bool, "operand of '!'").map(|_| Type::Bool),
+            Expr::Binary(op, l, r) => match op {
+                BinOp::Add | BinOp::Sub | BinOp::Mul | BinOp::Div => {
+                    expect(l, Type::Num, "operand of arithmetic")?;
+                    expect(r, Type::Num, "operand of arithmetic")?;
+                    Ok(Type::Num)
+                }
+                BinOp::Lt | BinOp::Le | BinOp::Gt | BinOp::Ge => {
+                    expect(l, Type::Num, "operand of comparison")?;
+                    expect(r, Type::Num, "operand of comparison")?;
+                    Ok(Type::Bool)
+                }
+                BinOp::Eq | BinOp::Ne => {
+                    let t = l.check(kind)?;
+                    expect(r, t, "right side of comparison")?;
+                    Ok(Type::Bool)
+                }
+                BinOp::And | BinOp::Or => {
+                    expect(l, Type::Bool, "operand of logic operator")?;
+                    expect(r, Type::Bool, "operand of logic operator")?;
+                    Ok(Type::Bool)
+                }
+            },
+            Expr::If(c, t, e) => {
+                expect(c, Type::Bool, "condition of if")?;
+                let then_type = t.check(kind)?;
+                expect(e, then_type, "else branch")?;
+                Ok(then_type)
+            }
+            Expr::Call(f, args) => {
+                let (params, name): (&[Type], &str) = match f {
+                    Func::Num => (&[Type::Str, Type::Num], "num"),
+                    Func::Min => (&[Type::Num, Type::Num], "min"),
+                    Func::Max => (&[Type::Num, Type::Num], "max"),
+                    Func::Abs => (&[Type::Num], "abs"),
+                };
+                if params.len() != args.len() {
+                    return Err(ScriptError(format!(
+                        "{}() takes {} arguments, {} given",
+                        name,
+                        params.len(),
+                        args.len()
+                    )));
+                }
+                for (a, p) in args.iter().zip(params) {
+                    expect(a, *p, &format!("argument of {}()", name))?;
+                }
+                Ok(Type::Num)
+            }
+        }
+    }
+
+    fn eval(&self, input: &Input) -> MetricResult<Value> {
+        let value = match self {
+            Expr::Lit(v) => v.clone(),
+            Expr::Tag(key) => match input {
+                Input::Tags(tags) => Value::Str(
+                    tags.get(key.as_str())
+                        .map(|v| v.to_string())
+                        .unwrap_or_default(),
+                ),
+                _ => unreachable!("inputs are checked when the script is parsed"),
+            },
+            Expr::Has(key) => match input {
+                Input::Tags(tags) => Value::Bool(tags.contains_key(key.as_str())),
+                _ => unreachable!("inputs are checked when the script is parsed"),
+            },
+            Expr::Node(endpoint, field) => match input {
+                Input::Nodes(source, target) => {
+                    let node = match endpoint {
+                        Endpoint::Source => source,
+                        Endpoint::Target => target,
+                    };
+                    Value::Num(match field {
+                        Field::Lat => node.lat,
+                        Field::Long => node.long,
+                        Field::Height => node.height,
+                    })
+                }
+                _ => unreachable!("inputs are checked when the script is parsed"),
+            },
+            Expr::Cost(name) => match input {
+                Input::Costs(costs, map) => {
+                    let index = *map.get(name).ok_or(MetricError::UnknownMetric)?;
+                    Value::Num(costs[index])
+                }
+                _ => unreachable!("inputs are checked when the script is parsed"),
+            },
+            Expr::Neg(e) => Value::Num(-e.eval(input)?.num()),
+            Expr::Not(e) => Value::Bool(!e.eval(input)?.bool()),
+            Expr::Binary(BinOp::And, l, r) => {
+                Value::Bool(l.eval(input)?.bool() && r.eval(input)?.bool())
+            }
+            Expr::Binary(BinOp::Or, l, r) => {
+                Value::Bool(l.eval(input)?.bool() || r.eval(input)?.bool())
+            }
+            Expr::Binary(BinOp::Eq, l, r) => Value::Bool(l.eval(input)? == r.eval(input)?),
+            Expr::Binary(BinOp::Ne, l, r) => Value::Bool(l.eval(input)? != r.eval(input)?),
+            Expr::Binary(op, l, r) => {
+                let l = l.eval(input)?.num();
+                let r = r.eval(input)?.num();
+                match op {
+                    BinOp::Add => Value::Num(l + r),
+                    BinOp::Sub => Value::Num(l - r),
+                    BinOp::Mul => Value::Num(l * r),
+                    BinOp::Div => Value::Num(l / r),
+                    BinOp::Lt => Value::Bool(l < r),
+                    BinOp::Le => Value::Bool(l <= r),
+                    BinOp::Gt => Value::Bool(l > r),
+                    BinOp::Ge => Value::Bool(l >= r),
+                    _ => unreachable!(),
+                }
+            }
+            Expr::If(c, t, e) => {
+                if c.eval(input)?.bool() {
+                    t.eval(input)?
+                } else {
+                    e.eval(input)?
+                }
+            }
+            Expr::Call(f, args) => {
+                let mut values = Vec::with_capacity(args.len());
+                for a in args {
+                    values.push(a.eval(input)?);
+                }
+                let mut values = values.into_iter();
+                let mut next = || values.next().expect("arguments are checked");
+                Value::Num(match f {
+                    Func::Num => {
+                        let s = next().str();
+                        let default = next().num();
+                        s.trim().parse().unwrap_or(default)
+                    }
+                    Func::Min => next().num().min(next().num()),
+                    Func::Max => next().num().max(next().num()),
+                    Func::Abs => next().num().abs(),
+                })
+            }
+        };
+        Ok(value)
+    }
+
+    fn collect_costs(&self, names: &mut Vec<String>) {
+        match self {
+            Expr::Cost(name) => {
+                if !names.contains(name) {
+                    names.push(name.clone());
+                }
+            }
+            Expr::Neg(e) | Expr::Not(e) => e.collect_costs(names),
+            Expr::Binary(_, l, r) => {
+                l.collect_costs(names);
+                r.collect_costs(names);
+            }
+            Expr::If(c, t, e) => {
+                c.collect_costs(names);
+                t.collect_costs(names);
+                e.collect_costs(names);
+            }
+            Expr::Call(_, args) => args.iter().for_each(|a| a.collect_costs(names)),
+            Expr::Lit(_) | Expr::Tag(_) | Expr::Has(_) | Expr::Node(..) => {}
+        }
+    }
+
+    fn uses_height(&self) -> bool {
+        match self {
+            Expr::Node(_, field) => *field == Field::Height,
+            Expr::Neg(e) | Expr::Not(e) => e.uses_height(),
+            Expr::Binary(_, l, r) => l.uses_height() || r.uses_height(),
+            Expr::If(c, t, e) => c.uses_height() || t.uses_height() || e.uses_height(),
+            Expr::Call(_, args) => args.iter().any(Expr::uses_height),
+            Expr::Lit(_) | Expr::Tag(_) | Expr::Has(_) | Expr::Cost(_) => false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Num(f64),
+    Str(String),
+    Ident(String),
+    Op(&'static str),
+}
+
+fn tokenize(source: &str) -> ScriptResult<Vec<(usize, Token)>> {
+    const OPS: [&str; 18] = [
+        "==", "!=", "<=", ">=", "&&", "||", "<", ">", "+", "-", "*", "/", "(", ")", ",", ".", "!",
+        "=",
+    ];
+    let mut tokens = Vec::new();
+    let chars: Vec<(usize, char)> = source.char_indices().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        let (pos, c) = chars[i];
+        if c.is_whitespace() {
+            i += 1;
+        } else if c.is_ascii_digit() {
+            while i < chars.len() && (chars[i].1.is_ascii_digit() || chars[i].1 == '.') {
+                i += 1;
+            }
+            let end = chars.get(i).map(|c| c.0).unwrap_or(source.len());
+            let text = &source[pos..end];
+            let n = text
+                .parse()
+                .map_err(|_| ScriptError(format!("invalid number '{}' at {}", text, pos)))?;
+            tokens.push((pos, Token::Num(n)));
+        } else if c == '"' {
+            i += 1;
+            let mut s = String::new();
+            loop {
+                match chars.get(i) {
+                    Some((_, '"')) => break,
+                    Some((_, '\\')) if i + 1 < chars.len() => {
+                        s.push(chars[i + 1].1);
+                        i += 2;
+                    }
+                    Some((_, c)) => {
+                        s.push(*c);
+                        i += 1;
+                    }
+                    None => return Err(ScriptError(format!("unterminated string at {}", pos))),
+                }
+            }
+            i += 1;
+            tokens.push((pos, Token::Str(s)));
+        } else if c.is_alphabetic() || c == '_' {
+            let start = i;
+            while i < chars.len() && (chars[i].1.is_alphanumeric() || chars[i].1 == '_') {
+                i += 1;
+            }
+            let ident: String = chars[start..i].iter().map(|c| c.1).collect();
+            tokens.push((pos, Token::Ident(ident)));
+        } else {
+            let rest = &source[pos..];
+            match OPS.iter().find(|op| rest.starts_with(*op)) {
+                Some(&"=") | None => {
+                    return Err(ScriptError(format!("unexpected '{}' at {}", c, pos)))
+                }
+                Some(op) => {
+                    tokens.push((pos, Token::Op(op)));
+                    i += op.chars().count();
+                }
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    len: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|t| &t.1)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens.get(self.pos).map(|t| t.0).unwrap_or(self.len)
+    }
+
+    fn error<T>(&self, expected: &str) -> ScriptResult<T> {
+        match self.peek() {
+            Some(t) => Err(ScriptError(format!(
+                "expected {} at {}, found {:?}",
+                expected,
+                self.position(),
+                t
+            ))),
+            None => Err(ScriptError(format!(
+                "expected {} at end of script",
+                expected
+            ))),
+        }
+    }
+
+    fn eat_op(&mut self, op: &'static str) -> bool {
+        if self.peek() == Some(&Token::Op(op)) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn eat_ident(&mut self, ident: &str) -> bool {
+        match self.peek() {
+            Some(Token::Ident(i)) if i == ident => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn expect_op(&mut self, op: &'static str) -> ScriptResult<()> {
+        if self.eat_op(op) {
+            Ok(())
+        } else {
+            self.error(&format!("'{}'", op))
+        }
+    }
+
+    fn expr(&mut self) -> ScriptResult<Expr> {
+        if self.eat_ident("if") {
+            let c = self.expr()?;
+            if !self.eat_ident("then") {
+                return self.error("'then'");
+            }
+            let t = self.expr()?;
+            if !self.eat_ident("else") {
+                return self.error("'else'");
+            }
+            let e = self.expr()?;
+            return Ok(Expr::If(Box::new(c), Box::new(t), Box::new(e)));
+        }
+        self.or()
+    }
+
+    fn or(&mut self) -> ScriptResult<Expr> {
+        let mut left = self.and()?;
+        while self.eat_op("||") {
+            let right = self.and()?;
+            left = Expr::Binary(BinOp::Or, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> ScriptResult<Expr> {
+        let mut left = self.cmp()?;
+        while self.eat_op("&&") {
+            let right = self.cmp()?;
+            left = Expr::Binary(BinOp::And, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn cmp(&mut self) -> ScriptResult<Expr> {
+        let left = self.sum()?;
+        let ops = [
+            ("==", BinOp::Eq),
+            ("!=", BinOp::Ne),
+            ("<=", BinOp::Le),
+            (">=", BinOp::Ge),
+            ("<", BinOp::Lt),
+            (">", BinOp::Gt),
+        ];
+        for (token, op) in &ops {
+            if self.eat_op(token) {
+                let right = self.sum()?;
+                return Ok(Expr::Binary(*op, Box::new(left), Box::new(right)));
+            }
+        }
+        Ok(left)
+    }
+
+    fn sum(&mut self) -> ScriptResult<Expr> {
+        let mut left = self.product()?;
+        loop {
+            let op = if self.eat_op("+") {
+                BinOp::Add
+            } else if self.eat_op("-") {
+                BinOp::Sub
+            } else {
+                return Ok(left);
+            };
+            let right = self.product()?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+    }
+
+    fn product(&mut self) -> ScriptResult<Expr> {
+        let mut left = self.unary()?;
+        loop {
+            let op = if self.eat_op("*") {
+                BinOp::Mul
+            } else if self.eat_op("/") {
+                BinOp::Div
+            } else {
+                return Ok(left);
+            };
+            let right = self.unary()?;
+            left = Expr::Binary(op, Box::new(left), Box::new(right));
+        }
+    }
+
+    fn unary(&mut self) -> ScriptResult<Expr> {
+        if self.eat_op("-") {
+            Ok(Expr::Neg(Box::new(self.unary()?)))
+        } else if self.eat_op("!") {
+            Ok(Expr::Not(Box::new(self.unary()?)))
+        } else {
+            self.primary()
+        }
+    }
+
+    fn string_literal(&mut self) -> ScriptResult<String> {
+        match self.peek().cloned() {
+            Some(Token::Str(s)) => {
+                self.pos += 1;
+                Ok(s)
+            }
+            _ => self.error("string literal"),
+        }
+    }
+
+    fn primary(&mut self) -> ScriptResult<Expr> {
+        let token = match self.peek().cloned() {
+            Some(t) => t,
+            None => return self.error("expression"),
+        };
+        self.pos += 1;
+        match token {
+            Token::Num(n) => Ok(Expr::Lit(Value::Num(n))),
+            Token::Str(s) => Ok(Expr::Lit(Value::Str(s))),
+            Token::Op("(") => {
+                let e = self.expr()?;
+                self.expect_op(")")?;
+                Ok(e)
+            }
+            Token::Ident(ident) => match ident.as_str() {
+                "true" => Ok(Expr::Lit(Value::Bool(true))),
+                "false" => Ok(Expr::Lit(Value::Bool(false))),
+                "source" | "target" => {
+                    let endpoint = if ident == "source" {
+                        Endpoint::Source
+                    } else {
+                        Endpoint::Target
+                    };
+                    self.expect_op(".")?;
+                    let field = if self.eat_ident("lat") {
+                        Field::Lat
+                    } else if self.eat_ident("long") {
+                        Field::Long
+                    } else if self.eat_ident("height") {
+                        Field::Height
+                    } else {
+                        return self.error("lat, long or height");
+                    };
+                    Ok(Expr::Node(endpoint, field))
+                }
+                "tag" | "has" | "cost" => {
+                    self.expect_op("(")?;
+                    let name = self.string_literal()?;
+                    self.expect_op(")")?;
+                    Ok(match ident.as_str() {
+                        "tag" => Expr::Tag(name),
+                        "has" => Expr::Has(name),
+                        _ => Expr::Cost(name),
+                    })
+                }
+                "num" | "min" | "max" | "abs" => {
+                    let func = match ident.as_str() {
+                        "num" => Func::Num,
+                        "min" => Func::Min,
+                        "max" => Func::Max,
+                        _ => Func::Abs,
+                    };
+                    self.expect_op("(")?;
+                    let mut args = Vec::new();
+                    if !self.eat_op(")") {
+                        loop {
+                            args.push(self.expr()?);
+                            if self.eat_op(")") {
+                                break;
+                            }
+                            self.expect_op(",")?;
+                        }
+                    }
+                    Ok(Expr::Call(func, args))
+                }
+                _ => {
+                    self.pos -= 1;
+                    self.error("expression")
+                }
+            },
+            Token::Op(_) => {
+                self.pos -= 1;
+                self.error("expression")
+            }
+        }
+    }
+}
+
+fn parse(source: &str) -> ScriptResult<Expr> {
+    let mut parser = Parser {
+        tokens: tokenize(source)?,
+        pos: 0,
+        len: source.len(),
+    };
+    let expr = parser.expr()?;
+    if parser.peek().is_some() {
+        return parser.error("end of script");
+    }
+    Ok(expr)
+}
+
+/// A metric calculated by a script. The script is parsed and type checked
+/// once when the metric is created.
+#[derive(Clone)]
+pub struct ScriptedMetric {
+    name: String,
+    kind: ScriptKind,
+    expr: Expr,
+    aggregation: Aggregation,
+}
+
+impl ScriptedMetric {
+    pub fn new(name: &str, kind: ScriptKind, source: &str) -> Result<Self, ScriptError> {
+        let expr = parse(source).map_err(|e| ScriptError(format!("{}: {}", name, e)))?;
+        match expr.check(kind) {
+            Ok(Type::Num) => Ok(ScriptedMetric {
+                name: name.to_owned(),
+                kind,
+                expr,
+                // tag values hold for the whole way, node and cost values
+                // for a single edge
+                aggregation: match kind {
+                    ScriptKind::Tag => Aggregation::WeightedMean,
+                    ScriptKind::Node | ScriptKind::Cost => Aggregation::Sum,
+                },
+            }),
+            Ok(t) => Err(ScriptError(format!(
+                "{}: script must calculate a number, found {}",
+                name, t
+            ))),
+            Err(e) => Err(ScriptError(format!("{}: {}", name, e))),
+        }
+    }
+
+    pub fn kind(&self) -> ScriptKind {
+        self.kind
+    }
+
+    pub fn set_aggregation(&mut self, aggregation: Aggregation) {
+        self.aggregation = aggregation;
+    }
+}
+
+impl FromStr for ScriptedMetric {
+    type Err = ScriptError;
+
+    /// Parses a script given as `name:kind:expression`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(3, ':');
+        match (parts.next(), parts.next(), parts.next()) {
+            (Some(name), Some(kind), Some(expr)) if !name.trim().is_empty() => {
+                ScriptedMetric::new(name.trim(), kind.trim().parse()?, expr)
+            }
+            _ => Err(ScriptError(format!(
+                "malformed script '{}', expected 'name:kind:expression'",
+                s
+            ))),
+        }
+    }
+}
+
+impl Metric for ScriptedMetric {
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn dependencies(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        self.expr.collect_costs(&mut names);
+        names
+    }
+
+    fn aggregation(&self) -> Aggregation {
+        self.aggregation
+    }
+
+    fn uses_height(&self) -> bool {
+        self.expr.uses_height()
+    }
+}
+
+impl TagMetric<f64> for ScriptedMetric {
+    fn calc(&self, tags: &Tags) -> MetricResult<f64> {
+        self.expr.eval(&Input::Tags(tags)).map(Value::num)
+    }
+}
+
+impl NodeMetric<f64> for ScriptedMetric {
+    fn calc(&self, source: &Node, target: &Node) -> MetricResult<f64> {
+        self.expr
+            .eval(&Input::Nodes(source, target))
+            .map(Value::num)
+    }
+}
+
+impl CostMetric<f64> for ScriptedMetric {
+    fn calc(&self, costs: &[f64], map: &MetricIndices) -> MetricResult<f64> {
+        self.expr.eval(&Input::Costs(costs, map)).map(Value::num)
+    }
+}
+
+#[test]
+fn tag_script() {
+    let m: ScriptedMetric =
+        "Cobbles:tag:if tag(\"surface\") == \"sett\" then 2 * num(tag(\"width\"), 1) else 1"
+            .parse()
+            .unwrap();
+    let mut tags = Tags::new();
+    assert_eq!(1.0, TagMetric::calc(&m, &tags).unwrap());
+    tags.insert("surface".into(), "sett".into());
+    tags.insert("width".into(), "3.5".into());
+    assert_eq!(7.0, TagMetric::calc(&m, &tags).unwrap());
+}
+
+#[test]
+fn node_and_cost_scripts() {
+    let m = ScriptedMetric::new(
+        "Descent",
+        ScriptKind::Node,
+        "max(source.height - target.height, 0)",
+    )
+    .unwrap();
+    let a = Node::new(1, 0.0, 0.0, 20.0);
+    let b = Node::new(2, 0.0, 0.0, 5.0);
+    assert_eq!(15.0, NodeMetric::calc(&m, &a, &b).unwrap());
+
+    let m = ScriptedMetric::new(
+        "Weighted",
+        ScriptKind::Cost,
+        "cost(\"Distance\") * -(-cost(\"BicycleUnsuitability\"))",
+    )
+    .unwrap();
+    assert_eq!(vec!["Distance", "BicycleUnsuitability"], m.dependencies());
+    let map: MetricIndices = vec![
+        ("BicycleUnsuitability".to_owned(), 0),
+        ("Distance".to_owned(), 1),
+    ]
+    .into_iter()
+    .collect();
+    assert_eq!(10.0, CostMetric::calc(&m, &[2.0, 5.0], &map).unwrap());
+}
+
+#[test]
+fn script_errors() {
+    let check = |kind, source| ScriptedMetric::new("M", kind, source).is_err();
+    assert!(check(ScriptKind::Tag, "source.height"));
+    assert!(check(ScriptKind::Node, "cost(\"Distance\")"));
+    assert!(check(ScriptKind::Tag, "tag(\"surface\")"));
+    assert!(check(ScriptKind::Tag, "1 + has(\"bicycle\")"));
+    assert!(check(ScriptKind::Tag, "if true then 1 else \"a\""));
+    assert!(check(ScriptKind::Tag, "min(1)"));
+    assert!(check(ScriptKind::Tag, "(1 + 2"));
+    assert!(check(ScriptKind::Tag, "1 = 2"));
+}
@@ -0,0 +1,421 @@
+/*
+Pbfextractor creates graph files for the cycle-routing projects from pbf and srtm data
+Copyright (C) 2018  Florian Barth
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+use super::elevation::{ElevationSource, MissingTiles, VoidFallback};
+use super::pbf::{ExtractError, Latitude, Longitude};
+#[cfg(test)]
+use super::testing::temp_dir;
+
+use memmap2::Mmap;
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::Path;
+use std::sync::{Arc, OnceLock, RwLock};
+
+/// The value SRTM uses for samples without data.
+const VOID: i16 = -32768;
+
+/// Number of samples per row of a tile with `size` bytes, if the tile is a
+/// square grid of 16 bit samples.
+fn samples_for_size(size: u64) -> Option<u64> {
+    let samples = ((size / 2) as f64).sqrt().round() as u64;
+    if samples >= 2 && samples * samples * 2 == size {
+        Some(samples)
+    } else {
+        None
+    }
+}
+
+/// Number of samples per row of tiles with a resolution of `arc_seconds`,
+/// e.g. 3601 for SRTM1 and 1201 for SRTM3.
+pub fn samples_for_resolution(arc_seconds: f64) -> Option<u64> {
+    let intervals = 3600.0 / arc_seconds;
+    if arc_seconds > 0.0 && intervals >= 1.0 && (intervals - intervals.round()).abs() < 1e-9 {
+        Some(intervals.round() as u64 + 1)
+    } else {
+        None
+    }
+}
+
+/// The name of the tile containing the coordinates. Tiles are named after
+/// their south west corner, e.g. `S34W071.hgt` covers latitudes from -34 to
+/// -33 and longitudes from -71 to -70.
+fn tile_name(lat: Latitude, lng: Longitude) -> String {
+    let south = lat.floor() as i64;
+    let west = lng.floor() as i64;
+    format!(
+        "{}{:02}{}{:03}.hgt",
+        if south < 0 { 'S' } else { 'N' },
+        south.abs(),
+        if west < 0 { 'W' } else { 'E' },
+        west.abs()
+    )
+}
+
+/// The south west corner of a tile given its file name.
+fn parse_tile_name(name: &str) -> Option<(i64, i64)> {
+    let name = name.strip_suffix(".hgt")?;
+    if name.len() != 7 || !name.is_ascii() {
+        return None;
+    }
+    let lat: i64 = name[1..3].parse().ok()?;
+    let lng: i64 = name[4..7].parse().ok()?;
+    let lat = match &name[0..1] {
+        "N" => lat,
+        "S" => -lat,
+        _ => return None,
+    };
+    let lng = match &name[3..4] {
+        "E" => lng,
+        "W" => -lng,
+        _ => return None,
+    };
+    Some((lat, lng))
+}
+
+/// A memory mapped `.hgt` file.
+struct Tile {
+    data: Mmap,
+    samples: u64,
+}
+
+impl Tile {
+    /// Opens a tile. Without `samples` the resolution is detected from the
+    /// file size.
+    fn open(path: &str, samples: Option<u64>) -> Result<Tile, ExtractError> {
+        let file = File::open(path).map_err(|e| ExtractError::Io(path.to_owned(), e))?;
+        // the tiles are only read and nobody is expected to modify them
+        // while the extraction runs
+        let data = unsafe { Mmap::map(&file) }.map_err(|e| ExtractError::Io(path.to_owned(), e))?;
+        let size = data.len() as u64;
+        let samples = match samples {
+            Some(samples) if samples * samples * 2 == size => samples,
+            Some(samples) => {
+                return Err(ExtractError::InvalidElevationData(
+                    path.to_owned(),
+                    format!("expected {0}x{0} samples, found {1} bytes", samples, size),
+                ))
+            }
+            None => samples_for_size(size).ok_or_else(|| {
+                ExtractError::InvalidElevationData(
+                    path.to_owned(),
+                    format!("{} bytes are no square grid of 16 bit samples", size),
+                )
+            })?,
+        };
+        Ok(Tile { data, samples })
+    }
+
+    /// Reads the big endian sample at the given row (counted from the
+    /// north) and column (counted from the west), `None` for voids.
+    fn sample(&self, row: u64, column: u64) -> Option<f64> {
+        let row = row.min(self.samples - 1);
+        let column = column.min(self.samples - 1);
+        let offset = ((row * self.samples + column) * 2) as usize;
+        match i16::from_be_bytes([self.data[offset], self.data[offset + 1]]) {
+            VOID => None,
+            height => Some(f64::from(height)),
+        }
+    }
+
+    /// Interpolates the height bilinearly from the four surrounding
+    /// samples, see `VoidFallback::height`.
+    fn height(
+        &self,
+        south: f64,
+        west: f64,
+        lat: Latitude,
+        lng: Longitude,
+        void: VoidFallback,
+    ) -> f64 {
+        let intervals = (self.samples - 1) as f64;
+        let row = (south + 1.0 - lat) * intervals;
+        let column = (lng - west) * intervals;
+        void.height(row, column, |r, c| self.sample(r, c))
+    }
+}
+
+/// Looks up heights in the SRTM tiles of a directory. Every tile is opened
+/// once and shared by all lookups, also from several threads.
+pub struct SrtmCache {
+    directory: String,
+    samples: Option<u64>,
+    void: VoidFallback,
+    missing: MissingTiles,
+    tiles: RwLock<HashMap<String, Arc<Tile>>>,
+    /// South west corners of all tiles in the directory, only read for
+    /// `MissingTiles::NearestTile`.
+    available: OnceLock<Vec<(i64, i64)>>,
+}
+
+impl SrtmCache {
+    /// Creates a cache for the tiles in `directory`. `samples` overrides
+    /// the number of samples per row, which is otherwise detected from the
+    /// size of each tile.
+    pub fn new(
+        directory: &str,
+        samples: Option<u64>,
+        void: VoidFallback,
+        missing: MissingTiles,
+    ) -> SrtmCache {
+        SrtmCache {
+            directory: directory.to_owned(),
+            samples,
+            void,
+            missing,
+            tiles: RwLock::new(HashMap::new()),
+            available: OnceLock::new(),
+        }
+    }
+
+    fn tile(&self, path: String, lat: Latitude, lng: Longitude) -> Result<Arc<Tile>, ExtractError> {
+        if let Some(tile) = self.tiles.read().expect("poisoned tile cache").get(&path) {
+            return Ok(Arc::clone(tile));
+        }
+        if !Path::new(&path).exists() {
+            return Err(ExtractError::MissingSrtmTile(path, lat, lng));
+        }
+        let tile = Arc::new(Tile::open(&path, self.samples)?);
+        self.tiles
+            .write()
+            .expect("poisoned tile cache")
+            .insert(path, Arc::clone(&tile));
+        Ok(tile)
+    }
+
+    /// The south west corner of the tile closest to the coordinates.
+    fn nearest_tile(
+        &self,
+        lat: Latitude,
+        lng: Longitude,
+    ) -> Result<Option<(i64, i64)>, ExtractError> {
+        if self.available.get().is_none() {
+            let entries = std::fs::read_dir(&self.directory)
+                .map_err(|e| ExtractError::Io(self.directory.clone(), e))?;
+            let corners = entries
+                .filter_map(|e| e.ok())
+                .filter_map(|e| e.file_name().to_str().and_then(parse_tile_name))
+                .collect();
+            // another thread may have been faster, both found the same tiles
+            let _ = self.available.set(corners);
+        }
+        let distance = |(south, west): (i64, i64)| {
+            let d_lat = (south as f64 - lat).max(lat - (south + 1) as f64).max(0.0);
+            let d_lng = (west as f64 - lng).max(lng - (west + 1) as f64).max(0.0);
+            d_lat * d_lat + d_lng * d_lng
+        };
+        Ok(self
+            .available
+            .get()
+            .expect("tiles were listed")
+            .iter()
+            .copied()
+            .min_by(|a, b| distance(*a).total_cmp(&distance(*b))))
+    }
+}
+
+impl ElevationSource for SrtmCache {
+    fn height(&self, lat: Latitude, lng: Longitude) -> Result<f64, ExtractError> {
+        let path = format!("{}/{}", self.directory, tile_name(lat, lng));
+        let (tile, south, west, lat, lng) = match self.tile(path, lat, lng) {
+            Ok(tile) => (tile, lat.floor(), lng.floor(), lat, lng),
+            Err(ExtractError::MissingSrtmTile(..)) if self.missing == MissingTiles::Zero => {
+                return Ok(0.0);
+            }
+            Err(e @ ExtractError::MissingSrtmTile(..))
+                if self.missing == MissingTiles::NearestTile =>
+            {
+                let (south, west) = match self.nearest_tile(lat, lng)? {
+                    Some(corner) => corner,
+                    None => return Err(e),
+                };
+                let (south, west) = (south as f64, west as f64);
+                let path = format!("{}/{}", self.directory, tile_name(south, west));
+                let lat = lat.max(south).min(south + 1.0);
+                let lng = lng.max(west).min(west + 1.0);
+                (self.tile(path, lat, lng)?, south, west, lat, lng)
+            }
+            Err(e) => return Err(e),
+        };
+
+        Ok(tile.height(south, west, lat, lng, self.void))
+    }
+}
+
+/// Writes a tile whose samples are `height(row, column)` to a new
+/// directory below the temporary directory.
+#[cfg(test)]
+fn write_test_tile(
+    directory: &str,
+    name: &str,
+    samples: u64,
+    height: impl Fn(u64, u64) -> i16,
+) -> String {
+    let directory = temp_dir(directory);
+    let mut data = Vec::new();
+    for row in 0..samples {
+        for column in 0..samples {
+            data.extend(&height(row, column).to_be_bytes());
+        }
+    }
+    std::fs::write(directory.join(name), data).unwrap();
+    directory.to_str().unwrap().to_owned()
+}
+
+#[test]
+fn detect_resolution() {
+    assert_eq!(Some(3601), samples_for_size(3601 * 3601 * 2));
+    assert_eq!(Some(1201), samples_for_size(1201 * 1201 * 2));
+    assert_eq!(None, samples_for_size(1201 * 1200 * 2));
+    assert_eq!(Some(3601), samples_for_resolution(1.0));
+    assert_eq!(Some(1201), samples_for_resolution(3.0));
+    assert_eq!(None, samples_for_resolution(7.0));
+}
+
+#[test]
+fn tiles_of_different_resolutions() {
+    // SRTM1 and SRTM3 sizes are covered by detect_resolution, small tiles
+    // of other sizes go through the same detection.
+    // heights grow by one meter per sample to the south and to the east
+    for (samples, directory) in &[
+        (5, "pbfextractor-srtm-coarse"),
+        (9, "pbfextractor-srtm-fine"),
+    ] {
+        let dir = write_test_tile(directory, "N48E009.hgt", *samples, |r, c| (r + c) as i16);
+        let intervals = (*samples - 1) as f64;
+        for resolution in [None, Some(*samples)] {
+            let cache =
+                SrtmCache::new(&dir, resolution, VoidFallback::Unknown, MissingTiles::Error);
+            assert_eq!(intervals, cache.height(48.0, 9.0).unwrap());
+            let height = cache.height(48.5, 9.25).unwrap();
+            assert!((height - intervals * 0.75).abs() < 1e-6);
+        }
+    }
+}
+
+#[test]
+fn resolution_override() {
+    let dir = write_test_tile("pbfextractor-srtm-override", "N48E009.hgt", 5, |r, _| {
+        r as i16
+    });
+    let cache = SrtmCache::new(&dir, Some(5), VoidFallback::Unknown, MissingTiles::Error);
+    assert!((cache.height(48.125, 9.5).unwrap() - 3.5).abs() < 1e-9);
+
+    let cache = SrtmCache::new(&dir, Some(1201), VoidFallback::Unknown, MissingTiles::Error);
+    assert!(cache.height(48.125, 9.5).is_err());
+}
+
+#[test]
+fn tile_names() {
+    assert_eq!("N48E009.hgt", tile_name(48.5, 9.2));
+    assert_eq!("N40W074.hgt", tile_name(40.7, -73.9));
+    assert_eq!("S34W071.hgt", tile_name(-33.4, -70.6));
+    assert_eq!("S34E151.hgt", tile_name(-33.8, 151.2));
+    assert_eq!("N00W001.hgt", tile_name(0.0, -0.5));
+    assert_eq!("S01E000.hgt", tile_name(-0.5, 0.0));
+    assert_eq!("N48E009.hgt", tile_name(48.0, 9.0));
+    assert_eq!("S01W001.hgt", tile_name(-1.0, -1.0));
+}
+
+#[test]
+fn heights_in_all_hemispheres() {
+    // row * 10 + column, rows counted from the north
+    let height = |r: u64, c: u64| (r * 10 + c) as i16;
+    let mut dir = String::new();
+    for name in &["N48E009.hgt", "N40W074.hgt", "S34W071.hgt", "S34E151.hgt"] {
+        dir = write_test_tile("pbfextractor-srtm-hemispheres", name, 5, height);
+    }
+    let cache = SrtmCache::new(&dir, None, VoidFallback::Unknown, MissingTiles::Error);
+    let check = |lat, lng, expected: f64| {
+        let height = cache.height(lat, lng).unwrap();
+        assert!(
+            (height - expected).abs() < 1e-6,
+            "{} != {}",
+            height,
+            expected
+        );
+    };
+
+    // north west corner, south west corner and inner points
+    check(48.999_999_999, 9.0, 0.0);
+    check(48.0, 9.0, 40.0);
+    check(48.75, 9.75, 13.0);
+
+    check(40.0, -74.0, 40.0);
+    check(40.5, -73.75, 21.0);
+    check(40.375, -73.4375, 27.25);
+
+    check(-34.0, -71.0, 40.0);
+    check(-33.25, -70.25, 13.0);
+
+    check(-34.0, 151.0, 40.0);
+    check(-33.75, 151.25, 31.0);
+}
+
+#[test]
+fn voids() {
+    // a void in the north west corner and a void block in the south east
+    let height = |r: u64, c: u64| match (r, c) {
+        (0, 0) => VOID,
+        (3..=4, 3..=4) => VOID,
+        _ => 100,
+    };
+    let dir = write_test_tile("pbfextractor-srtm-voids", "N48E009.hgt", 5, height);
+    let cache = SrtmCache::new(&dir, None, VoidFallback::Height(7.0), MissingTiles::Error);
+
+    assert_eq!(100.0, cache.height(48.9, 9.1).unwrap());
+    assert_eq!(100.0, cache.height(48.999_999_999, 9.0).unwrap());
+    assert_eq!(7.0, cache.height(48.1, 9.9).unwrap());
+
+    let cache = SrtmCache::new(&dir, None, VoidFallback::Unknown, MissingTiles::Error);
+    assert!(cache.height(48.1, 9.9).unwrap().is_nan());
+
+    // the closest valid samples around the void block
+    let cache = SrtmCache::new(&dir, None, VoidFallback::Neighbours, MissingTiles::Error);
+    assert_eq!(100.0, cache.height(48.1, 9.9).unwrap());
+
+    let dir = write_test_tile("pbfextractor-srtm-all-voids", "N48E009.hgt", 5, |_, _| VOID);
+    let cache = SrtmCache::new(&dir, None, VoidFallback::Neighbours, MissingTiles::Error);
+    assert_eq!(0.0, cache.height(48.5, 9.5).unwrap());
+}
+
+#[test]
+fn missing_tiles() {
+    let dir = write_test_tile("pbfextractor-srtm-missing", "N48E009.hgt", 5, |r, c| {
+        (r * 10 + c) as i16
+    });
+    let cache = |missing| SrtmCache::new(&dir, None, VoidFallback::Unknown, missing);
+
+    assert!(matches!(
+        cache(MissingTiles::Error).height(48.5, 10.5),
+        Err(ExtractError::MissingSrtmTile(..))
+    ));
+    assert_eq!(0.0, cache(MissingTiles::Zero).height(48.5, 10.5).unwrap());
+    // east of the tile the eastern column is used
+    assert_eq!(
+        24.0,
+        cache(MissingTiles::NearestTile).height(48.5, 10.5).unwrap()
+    );
+    // north west of the tile its north west corner
+    assert_eq!(
+        0.0,
+        cache(MissingTiles::NearestTile).height(50.5, 7.5).unwrap()
+    );
+    assert_eq!(Some((-34, -71)), parse_tile_name("S34W071.hgt"));
+    assert_eq!(None, parse_tile_name("N48E009.zip"));
+}
@@ -0,0 +1,39 @@
+/*
+Pbfextractor creates graph files for the cycle-routing projects from pbf and srtm data
+Copyright (C) 2018  Florian Barth
+
+This program is free software: you can redistribute it and/or modify
+it under the terms of the GNU General Public License as published by
+the Free Software Foundation, either version 3 of the License, or
+(at your option) any later version.
+
+This program is distributed in the hope that it will be useful,
+but WITHOUT ANY WARRANTY; without even the implied warranty of
+MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+GNU General Public License for more details.
+
+You should have received a copy of the GNU General Public License
+along with this program.  If not, see <https://www.gnu.org/licenses/>.
+ */
+//! Helpers shared by the tests of several modules.
+
+use osmpbfreader::Tags;
+
+use std::path::PathBuf;
+
+/// Builds the tags of a way or node from key-value pairs.
+pub fn tags(pairs: &[(&str, &str)]) -> Tags {
+    let mut tags = Tags::new();
+    for (k, v) in pairs {
+        tags.insert((*k).into(), (*v).into());
+    }
+    tags
+}
+
+/// Creates a directory for test fixtures below the temporary directory.
+/// The process id keeps concurrent test runs from sharing it.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let directory = std::env::temp_dir().join(format!("{}-{}", name, std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    directory
+}
@@ -23,6 +23,9 @@ pub struct Meters(pub f64);
 #[derive(PartialEq, Debug, Clone, Copy)]
 pub struct Kilometers(pub f64);
 
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Tonnes(pub f64);
+
 #[derive(PartialEq, Debug, Clone, Copy)]
 pub struct Seconds(pub f64);
 #[derive(PartialEq, Debug, Clone, Copy)]